@@ -1,9 +1,15 @@
-wit_bindgen_rust::export!("../../wit/record-processor.wit");
+// The generated export glue takes records flattened into many arguments.
+#![allow(clippy::too_many_arguments)]
+
+wit_bindgen::generate!({
+    path: "../../wit/wasmflow",
+    world: "wasmflow:processor/processor",
+});
+
 use chrono::{TimeZone, Utc};
-use record_processor::{FlowRecord, Status};
 struct RecordProcessor;
 
-impl record_processor::RecordProcessor for RecordProcessor {
+impl Guest for RecordProcessor {
     fn process_record(rec: FlowRecord) -> Status {
         let key = rec.key.as_ref().map_or("No Key", |v| {
             std::str::from_utf8(v.as_slice()).unwrap_or("UTF8 Error")
@@ -11,11 +17,14 @@ impl record_processor::RecordProcessor for RecordProcessor {
         let val = rec.value.as_ref().map_or("No Value", |v| {
             std::str::from_utf8(v.as_slice()).unwrap_or("UTF8 Error")
         });
-        let dt = Utc.timestamp(rec.timestamp / 1000, 0);
+        let dt = Utc.timestamp_millis_opt(rec.timestamp).single();
         println!("WASM Topic - {}", rec.topic);
         println!("WASM Partition - {}", rec.partition);
         println!("WASM Offset - {}", rec.offset);
-        println!("WASM Timestamp - {}", dt.to_rfc2822());
+        println!(
+            "WASM Timestamp - {}",
+            dt.map_or_else(|| "Invalid".to_string(), |dt| dt.to_rfc2822())
+        );
         println!("WASM Key - {key}");
         println!("WASM Value - {val}");
         for hdr in rec.headers {
@@ -27,3 +36,5 @@ impl record_processor::RecordProcessor for RecordProcessor {
         Status::Ok
     }
 }
+
+export!(RecordProcessor);
@@ -1,10 +1,15 @@
-wit_bindgen_rust::export!("../../wit/record-processor.wit");
-wit_bindgen_rust::import!("../../wit/s3-sink.wit");
+// The generated export glue takes records flattened into many arguments.
+#![allow(clippy::too_many_arguments)]
 
-use record_processor::{FlowRecord, Status};
+wit_bindgen::generate!({
+    path: "../../wit/wasmflow",
+    world: "wasmflow:processor/processor",
+});
+
+use wasmflow::processor::s3_sink;
 struct RecordProcessor;
 
-impl record_processor::RecordProcessor for RecordProcessor {
+impl Guest for RecordProcessor {
     fn process_record(rec: FlowRecord) -> Status {
         let key = rec.key.as_ref().map_or("No Key", |v| {
             std::str::from_utf8(v.as_slice()).unwrap_or("UTF8 Error")
@@ -15,10 +20,8 @@ impl record_processor::RecordProcessor for RecordProcessor {
         println!("WASM Key - {key}");
         println!("WASM Value - {val}");
         println!("WASM Partition - {}", rec.partition);
-        let res = s3_sink::write(rec.partition, val.as_bytes());
-        if res == s3_sink::Status::Error {
-            return Status::Error;
-        }
-        Status::Ok
+        s3_sink::write(rec.partition, val.as_bytes())
     }
 }
+
+export!(RecordProcessor);
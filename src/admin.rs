@@ -0,0 +1,41 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tracing::info;
+
+use crate::state::StateStore;
+
+/// Serves liveness and readiness probes. `/ready` fails while partition
+/// state is being restored.
+pub async fn serve(addr: SocketAddr, state_store: StateStore) -> anyhow::Result<()> {
+    let make_svc = make_service_fn(move |_conn| {
+        let state_store = state_store.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state_store = state_store.clone();
+                async move { Ok::<_, Infallible>(handle(&req, &state_store)) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_svc);
+    info!(admin_listen=%addr);
+    server.await?;
+    Ok(())
+}
+
+fn handle(req: &Request<Body>, state_store: &StateStore) -> Response<Body> {
+    let (status, body) = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/health") => (StatusCode::OK, "ok".to_string()),
+        (&Method::GET, "/ready") if state_store.is_ready() => (StatusCode::OK, "ready".to_string()),
+        (&Method::GET, "/ready") => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("restoring: {:?}", state_store.restore_progress()),
+        ),
+        _ => (StatusCode::NOT_FOUND, "not found".to_string()),
+    };
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = status;
+    resp
+}
@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+
+use crate::conf;
+
+/// Tracks the bytes of records being processed and decides when to pause and
+/// resume consumption, so that a slow sink does not make the flow buffer
+/// records without bound.
+#[derive(Debug)]
+pub struct Backpressure {
+    max_in_flight: u64,
+    max_queued: u64,
+    in_flight: AtomicU64,
+    paused: AtomicBool,
+}
+
+/// Bytes of a record being processed, released when dropped.
+pub struct InFlight {
+    backpressure: Arc<Backpressure>,
+    bytes: u64,
+}
+
+impl Backpressure {
+    pub fn new(cfg: &conf::BackpressureConfig) -> anyhow::Result<Self> {
+        if cfg.max_in_flight_bytes.is_none() && cfg.max_sink_queue_bytes.is_none() {
+            bail!("Backpressure needs max_in_flight_bytes or max_sink_queue_bytes");
+        }
+        Ok(Self {
+            max_in_flight: cfg.max_in_flight_bytes.unwrap_or(u64::MAX),
+            max_queued: cfg.max_sink_queue_bytes.unwrap_or(u64::MAX),
+            in_flight: AtomicU64::new(0),
+            paused: AtomicBool::new(false),
+        })
+    }
+
+    pub fn track(self: &Arc<Self>, bytes: u64) -> InFlight {
+        self.in_flight.fetch_add(bytes, Ordering::Relaxed);
+        InFlight {
+            backpressure: self.clone(),
+            bytes,
+        }
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Some(true)` when consumption should pause given the bytes
+    /// `queued` in sinks, `Some(false)` when it should resume, and `None` when
+    /// it should stay as it is.
+    pub fn update(&self, queued: u64) -> Option<bool> {
+        let in_flight = self.in_flight();
+        let paused = self.is_paused();
+        if !paused && (in_flight > self.max_in_flight || queued > self.max_queued) {
+            self.paused.store(true, Ordering::Relaxed);
+            Some(true)
+        } else if paused && in_flight <= self.max_in_flight / 2 && queued <= self.max_queued / 2 {
+            self.paused.store(false, Ordering::Relaxed);
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.backpressure
+            .in_flight
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_update() {
+        let backpressure = Arc::new(
+            Backpressure::new(&conf::BackpressureConfig {
+                max_in_flight_bytes: Some(100),
+                max_sink_queue_bytes: Some(1000),
+            })
+            .unwrap(),
+        );
+        let first = backpressure.track(80);
+        assert_eq!(backpressure.update(0), None);
+        let second = backpressure.track(30);
+        assert_eq!(backpressure.update(0), Some(true));
+        assert_eq!(backpressure.update(0), None);
+        drop(first);
+        assert_eq!(backpressure.in_flight(), 30);
+        // Both limits need to drain to half before resuming.
+        assert_eq!(backpressure.update(600), None);
+        assert_eq!(backpressure.update(500), Some(false));
+        assert!(!backpressure.is_paused());
+        assert_eq!(backpressure.update(1001), Some(true));
+        drop(second);
+    }
+}
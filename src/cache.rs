@@ -0,0 +1,225 @@
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+use wasmtime::{component::Component, Engine, Module, Precompiled};
+
+const PRECOMPILED_EXTENSION: &str = "cwasm";
+
+/// A compiled guest, either a component targeting the `wasmflow:processor`
+/// world or a legacy core module.
+#[derive(Clone)]
+pub enum Guest {
+    Component(Component),
+    Core(Module),
+}
+
+impl Guest {
+    fn compile(engine: &Engine, wasm: &[u8]) -> anyhow::Result<Self> {
+        if wasmparser::Parser::is_component(wasm) {
+            Ok(Self::Component(Component::new(engine, wasm)?))
+        } else {
+            Ok(Self::Core(Module::new(engine, wasm)?))
+        }
+    }
+
+    /// Safety: `bytes` must come from `serialize` or `precompile` with a
+    /// compatible engine, see `Module::deserialize`.
+    unsafe fn deserialize(engine: &Engine, bytes: &[u8]) -> anyhow::Result<Self> {
+        match engine.detect_precompiled(bytes) {
+            Some(Precompiled::Component) => {
+                Ok(Self::Component(Component::deserialize(engine, bytes)?))
+            }
+            Some(Precompiled::Module) => Ok(Self::Core(Module::deserialize(engine, bytes)?)),
+            None => anyhow::bail!("Not a precompiled module or component"),
+        }
+    }
+
+    fn serialize(&self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Component(c) => c.serialize(),
+            Self::Core(m) => m.serialize(),
+        }
+    }
+}
+
+/// On-disk cache of compiled guests keyed by module hash, wasmtime version
+/// and engine configuration.
+#[derive(Clone, Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    pub fn new(dir: &Path) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create module cache dir: {dir:?}"))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn load(&self, engine: &Engine, wasm: &[u8]) -> anyhow::Result<Guest> {
+        let path = self.dir.join(format!(
+            "{}.{PRECOMPILED_EXTENSION}",
+            cache_key(engine, wasm)
+        ));
+        if path.exists() {
+            let loaded = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                // Safety: the cache dir is only ever written by `store` below
+                // with serialized artifacts for the same engine config.
+                .and_then(|bytes| unsafe { Guest::deserialize(engine, &bytes) });
+            match loaded {
+                Ok(guest) => {
+                    debug!(module_cache_hit=?path);
+                    return Ok(guest);
+                }
+                Err(e) => warn!(module_cache_invalid=?path, error=%e),
+            }
+        }
+        let guest = Guest::compile(engine, wasm).with_context(|| "Could not compile module")?;
+        if let Err(e) = self.store(&path, &guest) {
+            warn!(module_cache_write_error=?e);
+        }
+        Ok(guest)
+    }
+
+    fn store(&self, path: &Path, guest: &Guest) -> anyhow::Result<()> {
+        let bytes = guest.serialize()?;
+        // Write to a temporary file first so that concurrent starts never
+        // observe a partially written artifact.
+        let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        fs::write(&tmp, bytes).with_context(|| format!("Could not write {tmp:?}"))?;
+        fs::rename(&tmp, path).with_context(|| format!("Could not rename {tmp:?}"))?;
+        info!(module_cache_write=?path);
+        Ok(())
+    }
+}
+
+fn cache_key(engine: &Engine, wasm: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(wasm);
+    // Covers the wasmtime version as well as the compiler and engine settings
+    // that affect the compiled artifact.
+    let mut compat = Sha256Hasher(hasher);
+    engine.precompile_compatibility_hash().hash(&mut compat);
+    format!("{:x}", compat.0.finalize())
+}
+
+/// Feeds `Hash` implementations to SHA-256, whose output, unlike that of
+/// `DefaultHasher`, does not change between Rust releases.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap_or_default())
+    }
+}
+
+/// A compiled guest along with the SHA-256 of the file it was loaded from.
+#[derive(Clone)]
+pub struct LoadedModule {
+    pub guest: Guest,
+    pub hash: String,
+}
+
+/// Loads the module at `path`, using `cache` to skip compilation when
+/// possible. Files with a `.cwasm` extension are treated as precompiled.
+pub fn load_module(
+    engine: &Engine,
+    path: &Path,
+    cache: Option<&ModuleCache>,
+) -> anyhow::Result<LoadedModule> {
+    let bytes = fs::read(path).with_context(|| format!("Could not read module: {path:?}"))?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+    let guest = if path.extension().is_some_and(|e| e == PRECOMPILED_EXTENSION) {
+        // Safety: `.cwasm` files are expected to come from `precompile`, as
+        // produced by `wasmflow compile` at image build time.
+        unsafe { Guest::deserialize(engine, &bytes) }
+            .with_context(|| format!("Could not load precompiled module: {path:?}"))?
+    } else {
+        match cache {
+            Some(cache) => cache.load(engine, &bytes),
+            None => Guest::compile(engine, &bytes).with_context(|| "Could not compile module"),
+        }
+        .with_context(|| format!("Could not create module: {path:?}"))?
+    };
+    Ok(LoadedModule { guest, hash })
+}
+
+/// Compiles the module or component at `input` ahead of time and writes the
+/// artifact to `output`, defaulting to `input` with a `.cwasm` extension.
+pub fn precompile(engine: &Engine, input: &Path, output: Option<&Path>) -> anyhow::Result<PathBuf> {
+    let output = output.map_or_else(
+        || input.with_extension(PRECOMPILED_EXTENSION),
+        Path::to_path_buf,
+    );
+    let wasm = fs::read(input).with_context(|| format!("Could not read module: {input:?}"))?;
+    let bytes = if wasmparser::Parser::is_component(&wasm) {
+        engine.precompile_component(&wasm)
+    } else {
+        engine.precompile_module(&wasm)
+    }
+    .with_context(|| format!("Could not compile module: {input:?}"))?;
+    fs::write(&output, bytes).with_context(|| format!("Could not write {output:?}"))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exports(guest: &Guest) -> Vec<String> {
+        match guest {
+            Guest::Core(m) => m.exports().map(|e| e.name().to_string()).collect(),
+            Guest::Component(_) => panic!("not a core module"),
+        }
+    }
+
+    #[test]
+    fn test_cache_key() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        let cache = ModuleCache::new(&dir)?;
+        let engine = Engine::default();
+        let (a, b) = (
+            br#"(module (func (export "a")))"#.as_slice(),
+            br#"(module (func (export "b")))"#.as_slice(),
+        );
+        let cached = |engine: &Engine, wasm: &[u8]| {
+            dir.join(format!(
+                "{}.{PRECOMPILED_EXTENSION}",
+                cache_key(engine, wasm)
+            ))
+        };
+
+        // A miss compiles the module and stores it under its key.
+        assert_eq!(exports(&cache.load(&engine, a)?), ["a"]);
+        assert!(cached(&engine, a).exists());
+        assert!(!cached(&engine, b).exists());
+
+        // A hit loads the stored artifact without compiling the module, so
+        // replacing the artifact shows up in the loaded guest.
+        let other = Guest::compile(&engine, b)?.serialize()?;
+        fs::write(cached(&engine, a), other)?;
+        assert_eq!(exports(&cache.load(&engine, a)?), ["b"]);
+
+        // An engine with other compiler settings does not share artifacts.
+        let mut config = wasmtime::Config::new();
+        config.cranelift_opt_level(wasmtime::OptLevel::None);
+        let other_engine = Engine::new(&config)?;
+        assert_ne!(cache_key(&engine, a), cache_key(&other_engine, a));
+        assert_eq!(exports(&cache.load(&other_engine, a)?), ["a"]);
+        assert!(cached(&other_engine, a).exists());
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+//! Host bindings for guests built as components targeting the versioned
+//! `wasmflow:processor` world in `wit/wasmflow`.
+
+wasmtime::component::bindgen!({
+    path: "wit/wasmflow",
+    world: "wasmflow:processor/processor",
+    async: true,
+    with: {
+        "wasmflow:processor/types": shared::wasmflow::processor::types,
+    },
+});
+
+/// Bindings only define the types their world refers to, and no world refers
+/// to every type of the `types` interface. They are all defined here instead,
+/// from a world using each of them, and shared by the bindings of every world.
+mod shared {
+    wasmtime::component::bindgen!({
+        path: "wit/wasmflow",
+        inline: "
+            package wasmflow:host;
+
+            world shared-types {
+                use wasmflow:processor/types@0.1.0.{flow-record, emitted-record, status, window};
+
+                export shared-types: func(rec: flow-record, emitted: emitted-record, window: window) -> status;
+            }
+        ",
+        world: "wasmflow:host/shared-types",
+    });
+}
+
+/// Bindings for the `windowed-processor` world. Its imports are resolved by
+/// name, so it is instantiated with the same linker as `processor`.
+pub mod windowed {
+    wasmtime::component::bindgen!({
+        path: "wit/wasmflow",
+        world: "wasmflow:processor/windowed-processor",
+        async: true,
+        with: {
+            "wasmflow:processor/types": super::types,
+            "wasmflow:processor/s3-sink": super::s3_sink,
+            "wasmflow:processor/sinks": super::sinks,
+            "wasmflow:processor/record-emitter": super::record_emitter,
+            "wasmflow:processor/state-store": super::state_store,
+            "wasmflow:processor/watermark": super::watermark,
+        },
+    });
+}
+
+/// Bindings for the `timed-processor` world, which extends `processor`.
+pub mod timed {
+    wasmtime::component::bindgen!({
+        path: "wit/wasmflow",
+        world: "wasmflow:processor/timed-processor",
+        async: true,
+        with: {
+            "wasmflow:processor/types": super::types,
+            "wasmflow:processor/s3-sink": super::s3_sink,
+            "wasmflow:processor/sinks": super::sinks,
+            "wasmflow:processor/record-emitter": super::record_emitter,
+            "wasmflow:processor/state-store": super::state_store,
+            "wasmflow:processor/watermark": super::watermark,
+            "wasmflow:processor/decoded": super::decoded,
+        },
+    });
+}
+
+/// Bindings for the `join-processor` world.
+pub mod join {
+    wasmtime::component::bindgen!({
+        path: "wit/wasmflow",
+        world: "wasmflow:processor/join-processor",
+        async: true,
+        with: {
+            "wasmflow:processor/types": super::types,
+            "wasmflow:processor/s3-sink": super::s3_sink,
+            "wasmflow:processor/sinks": super::sinks,
+            "wasmflow:processor/record-emitter": super::record_emitter,
+            "wasmflow:processor/state-store": super::state_store,
+            "wasmflow:processor/watermark": super::watermark,
+        },
+    });
+}
+
+/// Bindings for the `deduplicator` world.
+pub mod dedup {
+    wasmtime::component::bindgen!({
+        path: "wit/wasmflow",
+        world: "wasmflow:processor/deduplicator",
+        async: true,
+        with: {
+            "wasmflow:processor/types": super::types,
+        },
+    });
+}
+
+pub use self::timed::wasmflow::processor::timers;
+pub use self::wasmflow::processor::types::{EmittedRecord, Window};
+pub use self::wasmflow::processor::{
+    decoded, record_emitter, s3_sink, sinks, state_store, types, watermark,
+};
+
+use crate::record::Record;
+
+impl From<&Record> for FlowRecord {
+    fn from(rec: &Record) -> Self {
+        Self {
+            key: rec.key.clone(),
+            value: rec.value.clone(),
+            headers: rec.headers.clone(),
+            topic: rec.topic.clone(),
+            partition: rec.partition,
+            offset: rec.offset,
+            timestamp: rec.timestamp,
+        }
+    }
+}
@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
 use educe::Educe;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Formatter};
 use std::fs;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use tracing::info;
 
@@ -10,6 +12,28 @@ fn fmt_redact(_s: &str, f: &mut Formatter) -> fmt::Result {
     f.write_str("** Redacted **")
 }
 
+fn fmt_redact_option(s: &Option<String>, f: &mut Formatter) -> fmt::Result {
+    match s {
+        Some(_) => f.write_str("Some(** Redacted **)"),
+        None => f.write_str("None"),
+    }
+}
+
+/// Redacts the values of properties whose names suggest a secret.
+fn fmt_properties(properties: &BTreeMap<String, String>, f: &mut Formatter) -> fmt::Result {
+    const SECRETS: &[&str] = &["password", "secret", "pem", "config", "keytab"];
+    f.debug_map()
+        .entries(properties.iter().map(|(k, v)| {
+            let value = if SECRETS.iter().any(|s| k.contains(s)) {
+                "** Redacted **"
+            } else {
+                v.as_str()
+            };
+            (k, value)
+        }))
+        .finish()
+}
+
 #[derive(Educe, Serialize, Deserialize)]
 #[educe(Debug)]
 pub enum SaslConfig {
@@ -20,33 +44,493 @@ pub enum SaslConfig {
         #[educe(Debug(method = "fmt_redact"))]
         password: String,
     },
+    ScramSha256 {
+        #[educe(Debug(method = "fmt_redact"))]
+        username: String,
+        #[educe(Debug(method = "fmt_redact"))]
+        password: String,
+    },
+    ScramSha512 {
+        #[educe(Debug(method = "fmt_redact"))]
+        username: String,
+        #[educe(Debug(method = "fmt_redact"))]
+        password: String,
+    },
+    OAuthBearer(TokenProvider),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Source of OAUTHBEARER tokens. Tokens are fetched again shortly before
+/// they expire.
+#[derive(Clone, Educe, Serialize, Deserialize)]
+#[educe(Debug)]
+pub enum TokenProvider {
+    /// OpenID Connect client credentials grant.
+    Oidc {
+        token_endpoint_url: String,
+        client_id: String,
+        #[educe(Debug(method = "fmt_redact"))]
+        client_secret: String,
+        #[serde(default)]
+        scope: Option<String>,
+    },
+    /// Runs `command`, which must print an OAuth 2 token response: a JSON
+    /// object with an `access_token` and, unless the token is a JWT with an
+    /// `exp` claim, `expires_in` seconds.
+    Command {
+        command: Vec<String>,
+        /// Principal the tokens are for. Defaults to their `sub` claim.
+        #[serde(default)]
+        principal: Option<String>,
+    },
+    /// Unsigned tokens for `principal`, for development clusters only.
+    Unsecured { principal: String },
+}
+
+/// TLS settings for connections to the brokers. Without them, SASL
+/// connections use TLS with the system CA store and other connections are
+/// plaintext.
+#[derive(Educe, Default, Serialize, Deserialize)]
+#[educe(Debug)]
+pub struct TlsConfig {
+    /// CA bundle to verify the brokers with instead of the system store.
+    #[serde(default)]
+    pub ca_location: Option<PathBuf>,
+    /// Client certificate and key, for mutual TLS.
+    #[serde(default)]
+    pub certificate_location: Option<PathBuf>,
+    #[serde(default)]
+    pub key_location: Option<PathBuf>,
+    #[serde(default)]
+    #[educe(Debug(method = "fmt_redact_option"))]
+    pub key_password: Option<String>,
+}
+
+// Sources are read once from the config, their size does not matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Educe, Serialize, Deserialize)]
+#[educe(Debug)]
 pub enum Source {
     Kafka {
         brokers: Vec<String>,
         group_id: String,
-        topic: String,
-        batch_size: i32,
+        #[serde(default)]
+        topic: Option<String>,
+        /// Subscribed to along with `topic`. Entries starting with `^` are
+        /// regular expressions, and topics created later that match them are
+        /// picked up when the client refreshes its metadata.
+        #[serde(default)]
+        topics: Vec<String>,
+        /// Ignored, tune fetching through `properties` instead.
+        #[serde(default)]
+        batch_size: Option<i32>,
         sasl: SaslConfig,
+        #[serde(default)]
+        tls: Option<TlsConfig>,
+        /// librdkafka consumer properties, applied over the defaults.
+        #[serde(default)]
+        #[educe(Debug(method = "fmt_properties"))]
+        properties: BTreeMap<String, String>,
+        /// Where to start partitions the group has no committed offset for,
+        /// applied as they are assigned. `wasmflow reset-offsets` moves a
+        /// stopped group there even where it has committed offsets, to
+        /// reprocess records.
+        #[serde(default)]
+        start_from: Option<StartFrom>,
+        /// Partition assignment strategy, librdkafka's default when unset.
+        /// State is kept by partition number, so sources reading several
+        /// topics or a pattern use range assignment and may not set another,
+        /// and the topics must have as many partitions.
+        #[serde(default)]
+        assignment: Option<Assignment>,
+        /// Decodes values written by Schema Registry serializers before they
+        /// reach the processors.
+        #[serde(default)]
+        schema_registry: Option<SchemaRegistryConfig>,
+    },
+    /// Newline-delimited records read from files, each line becoming a
+    /// record whose topic is the file path and whose offset is the byte
+    /// position of the line. Cannot be combined with other sources.
+    File {
+        /// File paths or glob patterns. Rotated files must not match them,
+        /// or they are read again under their new name.
+        paths: Vec<String>,
+        /// Keeps reading lines appended to the files, reopens files that are
+        /// rotated or truncated, and picks up new files matching the paths.
+        /// Without it the flow ends once the files are read.
+        #[serde(default)]
+        follow: bool,
+        /// Where the position of each file is saved, with state checkpoints
+        /// when they are configured. Files are read from the start when
+        /// unset.
+        #[serde(default)]
+        state_file: Option<PathBuf>,
+        /// How often followed files are checked for new lines and rotation.
+        #[serde(default)]
+        poll_interval_ms: Option<u64>,
+    },
+    /// Lines read from standard input, each becoming a record of topic
+    /// `stdin` whose offset is the byte position of the line. The flow ends
+    /// with the input. Cannot be combined with other sources.
+    Stdin {
+        #[serde(default)]
+        format: LineFormat,
     },
+    /// Records posted to `/records` on a local HTTP endpoint, as a JSON
+    /// record or an array of them, as NDJSON, or as a raw body holding the
+    /// value. Requests are answered once their records went through the
+    /// processors and sinks, S3 sinks uploading what they buffered first.
+    /// Cannot be combined with other sources.
+    Http {
+        listen: SocketAddr,
+        /// Topic of the records, defaults to `http`.
+        #[serde(default)]
+        topic: Option<String>,
+        /// Records being processed beyond which requests are rejected with
+        /// 429 Too Many Requests.
+        #[serde(default)]
+        max_in_flight: Option<usize>,
+    },
+}
+
+/// How records are read from and written to lines of text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineFormat {
+    /// The line is the value of the record.
+    #[default]
+    Value,
+    /// The line is a JSON object with optional `key`, `value` and `headers`
+    /// fields, the headers being an object of strings. Values that are not
+    /// strings are kept as JSON text.
+    Json,
+}
+
+#[derive(Educe, Serialize, Deserialize)]
+#[educe(Debug)]
+pub struct SchemaRegistryConfig {
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    #[educe(Debug(method = "fmt_redact_option"))]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub output: DecodeOutput,
+    /// Sink receiving the records that cannot be decoded, with the reason in
+    /// a `wasmflow-error` header. They are dropped when unset.
+    #[serde(default)]
+    pub dead_letter: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DecodeOutput {
+    /// The value is replaced by its canonical JSON.
+    #[default]
+    Json,
+    /// The value is kept and guests read the decoded value through the
+    /// `decoded` interface.
+    Structured,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Assignment {
+    Range,
+    RoundRobin,
+    /// Incremental rebalancing, which moves only the partitions that change
+    /// owner instead of revoking the whole assignment.
+    CooperativeSticky,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StartFrom {
+    Earliest,
+    Latest,
+    /// The first records at or after an RFC 3339 timestamp.
+    Timestamp(String),
+    /// Offsets by partition, applied to every topic of the source. Other
+    /// partitions keep their position.
+    Offsets(BTreeMap<i32, i64>),
+}
+
+impl Source {
+    /// Topic names and patterns a Kafka source subscribes to.
+    pub fn topics(&self) -> Vec<&str> {
+        match self {
+            Source::Kafka { topic, topics, .. } => {
+                topic.iter().chain(topics).map(String::as_str).collect()
+            }
+            Source::File { .. } | Source::Stdin { .. } | Source::Http { .. } => Vec::new(),
+        }
+    }
+
+    /// The topic of a source subscribed to exactly one topic by name.
+    pub fn topic(&self) -> Option<&str> {
+        match self.topics().as_slice() {
+            [topic] if !topic.starts_with('^') => Some(topic),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Sink {
     None,
     S3 {
+        /// Name used by routes and guests, defaults to `s3`.
+        #[serde(default)]
+        name: Option<String>,
         region: String,
         bucket: String,
         key_prefix: String,
         file_size: u16,
+        #[serde(default)]
+        rate_limit: Option<SinkRateLimits>,
     },
+    Kafka {
+        /// Name used by routes and guests, defaults to `kafka`.
+        #[serde(default)]
+        name: Option<String>,
+        brokers: Vec<String>,
+        topic: String,
+        sasl: SaslConfig,
+        #[serde(default)]
+        tls: Option<TlsConfig>,
+        #[serde(default)]
+        rate_limit: Option<SinkRateLimits>,
+    },
+    /// Writes each record as a line to standard output.
+    Stdout {
+        /// Name used by routes and guests, defaults to `stdout`.
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        format: LineFormat,
+    },
+}
+
+impl Sink {
+    pub fn name(&self) -> String {
+        match self {
+            Sink::None => "none".to_string(),
+            Sink::S3 { name, .. } => name.clone().unwrap_or_else(|| "s3".to_string()),
+            Sink::Kafka { name, .. } => name.clone().unwrap_or_else(|| "kafka".to_string()),
+            Sink::Stdout { name, .. } => name.clone().unwrap_or_else(|| "stdout".to_string()),
+        }
+    }
+}
+
+/// Token bucket refilled at `per_second` and holding at most `burst` tokens,
+/// which defaults to `per_second`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub per_second: u64,
+    #[serde(default)]
+    pub burst: Option<u64>,
+}
+
+/// Limits on what a sink writes. S3 sinks count uploads as requests.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SinkRateLimits {
+    #[serde(default)]
+    pub bytes: Option<RateLimit>,
+    #[serde(default)]
+    pub requests: Option<RateLimit>,
+}
+
+/// Pauses the assigned partitions while either limit is exceeded, and
+/// resumes them once both are back under half of their limit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackpressureConfig {
+    /// Bytes of records being processed.
+    #[serde(default)]
+    pub max_in_flight_bytes: Option<u64>,
+    /// Bytes sinks are writing: records waiting for Kafka to acknowledge
+    /// them and S3 files being uploaded. Records buffered for a file that
+    /// is not full yet do not count, as they only drain with more input.
+    #[serde(default)]
+    pub max_sink_queue_bytes: Option<u64>,
+}
+
+/// Sends the records leaving a pipeline that match all of the given
+/// conditions to `sinks`. A record matching several routes is sent to each.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Route {
+    pub sinks: Vec<String>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    #[serde(default)]
+    pub key_prefix: Option<String>,
+    #[serde(default)]
+    pub header: Option<HeaderMatch>,
+}
+
+/// Matches records carrying header `name`, with `value` if given.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeaderMatch {
+    pub name: String,
+    #[serde(default)]
+    pub value: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Processor {
     pub module_path: PathBuf,
+    /// Directory for compiled module artifacts. Compilation is skipped on
+    /// startup when a matching artifact is found.
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    /// How often to check `module_path` for a new module. Reloads can also be
+    /// requested at any time with SIGHUP.
+    #[serde(default)]
+    pub reload_interval_secs: Option<u64>,
+    /// Name used in logs and metrics, defaults to the module file name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Pipelines that each receive a copy of every record this processor
+    /// emits, in addition to the processor that follows it.
+    #[serde(default)]
+    pub branches: Vec<Vec<Processor>>,
+    /// Buffers records into event-time windows and passes each window to the
+    /// module's `on-window-close` export instead of calling `process-record`.
+    #[serde(default)]
+    pub window: Option<WindowConfig>,
+    /// Drops records whose id was already seen within the horizon before
+    /// they reach the module. Needs a `state` path to persist seen ids.
+    #[serde(default)]
+    pub dedup: Option<DedupConfig>,
+}
+
+impl Processor {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| {
+            self.module_path
+                .file_stem()
+                .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum WindowKind {
+    Tumbling {
+        size_ms: u64,
+    },
+    Hopping {
+        size_ms: u64,
+        advance_ms: u64,
+    },
+    /// Windows per key that extend while records arrive less than `gap_ms`
+    /// apart.
+    Session {
+        gap_ms: u64,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowConfig {
+    pub kind: WindowKind,
+    /// How long past its end a window keeps accepting records. Windows close
+    /// once the watermark passes `end + allowed_lateness_ms`.
+    #[serde(default)]
+    pub allowed_lateness_ms: u64,
+    #[serde(default)]
+    pub late_records: LateRecords,
+}
+
+/// Handling of records that arrive after all of their windows closed.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub enum LateRecords {
+    #[default]
+    Drop,
+    /// Feeds late records to a pipeline of their own.
+    SideOutput(Vec<Processor>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DedupConfig {
+    #[serde(default)]
+    pub id: DedupId,
+    /// How long after a record its id is remembered, in event time.
+    pub horizon_ms: u64,
+}
+
+/// What identifies duplicate records.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub enum DedupId {
+    #[default]
+    Key,
+    Header(String),
+    /// The id returned by the module's `dedup-id` export.
+    Guest,
+}
+
+/// Joins the records of the first two sources by key. Both topics must have
+/// the same number of partitions and be partitioned by the join key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinConfig {
+    pub kind: JoinKind,
+    /// Called through its `process-join` export with every matched pair. The
+    /// records it emits are fed to `processors`.
+    pub processor: Processor,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum JoinKind {
+    /// Keeps the latest record of the second source by key, and joins each
+    /// record of the first source with it.
+    Table,
+    /// Buffers both sources and joins records whose timestamps are at most
+    /// `within_ms` apart.
+    Window { within_ms: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateConfig {
+    /// Directory of the embedded store holding guest state.
+    pub path: PathBuf,
+    /// Compacted topic every state write is mirrored to. Local state for a
+    /// partition is rebuilt from it when the partition is assigned.
+    #[serde(default)]
+    pub changelog_topic: Option<String>,
+    /// Periodic snapshots of the store taken together with the source
+    /// offsets. The latest snapshot is preferred over a changelog replay.
+    #[serde(default)]
+    pub checkpoint: Option<CheckpointConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    pub interval_secs: u64,
+    pub target: CheckpointTarget,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CheckpointTarget {
+    Local {
+        path: PathBuf,
+    },
+    S3 {
+        region: String,
+        bucket: String,
+        key_prefix: String,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatermarkConfig {
+    /// How far a partition's watermark trails the highest timestamp seen in
+    /// it, i.e. how much out of order records may arrive.
+    #[serde(default)]
+    pub max_out_of_orderness_ms: u64,
+    /// Partitions without records for this long stop holding back the flow
+    /// watermark.
+    #[serde(default)]
+    pub idle_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Address of the HTTP server exposing `/health` and `/ready`.
+    pub listen: SocketAddr,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,6 +538,113 @@ pub struct FlowConfig {
     pub sources: Vec<Source>,
     pub sinks: Vec<Sink>,
     pub processors: Vec<Processor>,
+    #[serde(default)]
+    pub state: Option<StateConfig>,
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    #[serde(default)]
+    pub watermark: WatermarkConfig,
+    #[serde(default)]
+    pub join: Option<JoinConfig>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    /// Limits the records consumed per second. Consumption slows down to the
+    /// limit, no records are dropped.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub backpressure: Option<BackpressureConfig>,
+}
+
+impl FlowConfig {
+    /// A flow piping the records on stdin through a single module to stdout.
+    pub fn pipe(module_path: PathBuf, format: LineFormat) -> Self {
+        let processor = Processor {
+            module_path,
+            cache_dir: None,
+            reload_interval_secs: None,
+            name: None,
+            branches: Vec::new(),
+            window: None,
+            dedup: None,
+        };
+        let stdout = Sink::Stdout { name: None, format };
+        let route = Route {
+            sinks: vec![stdout.name()],
+            topic: None,
+            key_prefix: None,
+            header: None,
+        };
+        Self {
+            sources: vec![Source::Stdin { format }],
+            sinks: vec![stdout],
+            processors: vec![processor],
+            state: None,
+            admin: None,
+            watermark: WatermarkConfig::default(),
+            join: None,
+            routes: vec![route],
+            rate_limit: None,
+            backpressure: None,
+        }
+    }
+
+    /// Fails if two processors have the same name, since their state is kept
+    /// by name and would be shared.
+    fn check_processor_names(&self) -> Result<()> {
+        fn names(processors: &[Processor], found: &mut Vec<String>) {
+            for processor in processors {
+                found.push(processor.name());
+                for branch in &processor.branches {
+                    names(branch, found);
+                }
+                if let Some(WindowConfig {
+                    late_records: LateRecords::SideOutput(side),
+                    ..
+                }) = &processor.window
+                {
+                    names(side, found);
+                }
+            }
+        }
+        let mut found = Vec::new();
+        if let Some(join) = &self.join {
+            names(std::slice::from_ref(&join.processor), &mut found);
+        }
+        names(&self.processors, &mut found);
+        let mut seen = BTreeSet::new();
+        for name in found {
+            if !seen.insert(name.clone()) {
+                anyhow::bail!("Processor name {name:?} is used more than once");
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if Kafka sources reading several topics or a pattern ask for
+    /// another assignment than range, which is the only one giving a member
+    /// the same partition numbers of every topic.
+    fn check_assignment(&self) -> Result<()> {
+        let topics: Vec<&str> = self.sources.iter().flat_map(Source::topics).collect();
+        if topics.len() < 2 && !topics.iter().any(|t| t.starts_with('^')) {
+            return Ok(());
+        }
+        for source in &self.sources {
+            if let Source::Kafka {
+                assignment: Some(assignment),
+                ..
+            } = source
+            {
+                if *assignment != Assignment::Range {
+                    anyhow::bail!(
+                        "Sources reading several topics or a pattern must use Range \
+                         assignment, not {assignment:?}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn read_config() -> Result<FlowConfig> {
@@ -67,6 +658,9 @@ pub fn read_config_file(fname: &str) -> Result<FlowConfig> {
         fs::read_to_string(fname).with_context(|| format!("Error reading conf file {fname}"))?;
     let conf: FlowConfig = serde_yaml::from_str(&yaml_str)
         .with_context(|| format!("Error parsing YAML conf file {fname}"))?;
+    conf.check_processor_names()
+        .and_then(|()| conf.check_assignment())
+        .with_context(|| format!("Invalid conf file {fname}"))?;
     info!(conf=?conf);
     Ok(conf)
 }
@@ -76,21 +670,34 @@ mod tests {
     use super::*;
     #[test]
     fn test_read_config() {
-        let cfg = read_config_file("./src/conf/wasmflow.yml").unwrap();
+        let mut cfg = read_config_file("./src/conf/wasmflow.yml").unwrap();
         assert_eq!(cfg.sources.len(), 1);
         match &cfg.sources[0] {
             Source::Kafka {
                 brokers,
                 group_id,
                 topic,
+                topics,
                 batch_size,
                 sasl,
+                tls,
+                properties,
+                start_from,
+                assignment,
+                schema_registry,
             } => {
                 assert_eq!(brokers.len(), 1);
                 assert_eq!(brokers[0], "my-broker.confluent.cloud:9092");
                 assert_eq!(group_id, "wasmflow-group");
-                assert_eq!(topic, "my-topic");
-                assert_eq!(*batch_size, 1000000);
+                assert_eq!(topic.as_deref(), Some("my-topic"));
+                assert!(topics.is_empty());
+                assert!(batch_size.is_none());
+                assert!(tls.is_none());
+                assert_eq!(properties["fetch.max.bytes"], "52428800");
+                assert_eq!(properties["isolation.level"], "read_committed");
+                assert!(start_from.is_none());
+                assert_eq!(*assignment, Some(Assignment::CooperativeSticky));
+                assert!(schema_registry.is_none());
                 assert!(matches!(
                     sasl,
                     SaslConfig::Plain {
@@ -99,24 +706,74 @@ mod tests {
                     }
                 ));
             }
+            _ => {
+                panic!("Incorrect source config");
+            }
         }
+        assert_eq!(cfg.sources[0].topic(), Some("my-topic"));
+        if let Source::Kafka { properties, .. } = &mut cfg.sources[0] {
+            properties.insert("ssl.key.password".to_string(), "key-secret".to_string());
+        }
+        assert!(!format!("{:?}", cfg.sources[0]).contains("key-secret"));
         assert_eq!(cfg.sinks.len(), 1);
         match &cfg.sinks[0] {
             Sink::S3 {
+                name,
                 region,
                 bucket,
                 key_prefix,
                 file_size,
+                rate_limit,
             } => {
                 assert_eq!(region, "us-east-1");
                 assert_eq!(bucket, "wasmtime-sink");
                 assert_eq!(key_prefix, "my-stream");
                 assert_eq!(*file_size, 4096);
+                assert!(name.is_none());
+                assert!(rate_limit.is_none());
             }
             _ => {
                 panic!("Incorrect sink config");
             }
         }
         assert_eq!(cfg.processors.len(), 1);
+        assert_eq!(cfg.processors[0].name(), "wasm_s3_sink");
+        assert!(cfg.processors[0].branches.is_empty());
+        assert!(cfg.processors[0].window.is_none());
+        assert!(cfg.join.is_none());
+        assert!(cfg.routes.is_empty());
+        assert!(cfg.processors[0].dedup.is_none());
+        assert_eq!(
+            cfg.processors[0].cache_dir,
+            Some(PathBuf::from("./target/wasmflow-cache"))
+        );
+        // Processors default to the name of their module file.
+        cfg.processors.push(Processor {
+            module_path: cfg.processors[0].module_path.clone(),
+            cache_dir: None,
+            reload_interval_secs: None,
+            name: None,
+            branches: Vec::new(),
+            window: None,
+            dedup: None,
+        });
+        assert!(cfg.check_processor_names().is_err());
+        cfg.processors[1].name = Some("second".to_string());
+        assert!(cfg.check_processor_names().is_ok());
+        // Cooperative-sticky assignment is rejected with several topics.
+        assert!(cfg.check_assignment().is_ok());
+        if let Source::Kafka { topics, .. } = &mut cfg.sources[0] {
+            topics.push("other-topic".to_string());
+        }
+        assert!(cfg.check_assignment().is_err());
+        let state = cfg.state.unwrap();
+        assert_eq!(state.path, PathBuf::from("./target/wasmflow-state"));
+        assert!(matches!(
+            state.checkpoint,
+            Some(CheckpointConfig {
+                interval_secs: 60,
+                target: CheckpointTarget::Local { .. }
+            })
+        ));
     }
 }
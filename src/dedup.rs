@@ -0,0 +1,127 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::conf::{self, DedupId};
+use crate::record::Record;
+use crate::state::{decode_i64, encode_i64, StateStore};
+
+/// Drops records whose id was seen within the horizon. Seen ids live in the
+/// state store next to the guest state, so they are kept on disk and
+/// checkpointed with it. A `{processor}#seen` tree maps each id to the time
+/// it was first seen, and a `{processor}#seen-by-time` index is used to
+/// forget ids once the horizon has passed.
+pub struct Dedup {
+    pub id: DedupId,
+    horizon: i64,
+    deduplicated: AtomicU64,
+    // Records of the same partition are processed concurrently.
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl Dedup {
+    pub fn new(cfg: &conf::DedupConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: cfg.id.clone(),
+            horizon: i64::try_from(cfg.horizon_ms).with_context(|| "horizon_ms is out of range")?,
+            deduplicated: AtomicU64::new(0),
+            lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Id of `rec` unless it is computed by the guest.
+    pub fn record_id<'a>(&self, rec: &'a Record) -> Option<&'a [u8]> {
+        match &self.id {
+            DedupId::Key => rec.key.as_deref(),
+            DedupId::Header(name) => rec
+                .headers
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_slice()),
+            DedupId::Guest => None,
+        }
+    }
+
+    /// Records dropped so far.
+    pub fn deduplicated(&self) -> u64 {
+        self.deduplicated.load(Ordering::Relaxed)
+    }
+
+    /// Remembers `id` as seen at `timestamp`, or the current time for records
+    /// without one. Returns true if it had been seen within the horizon.
+    /// Ids older than the horizon at `watermark` are forgotten first.
+    pub async fn is_duplicate(
+        &self,
+        store: &StateStore,
+        processor: &str,
+        partition: i32,
+        id: &[u8],
+        timestamp: i64,
+        watermark: Option<i64>,
+    ) -> anyhow::Result<bool> {
+        let timestamp = if timestamp >= 0 {
+            timestamp
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)?
+                .as_millis()
+                .try_into()?
+        };
+        let _guard = self.lock.lock().await;
+        let seen = store.partition(&format!("{processor}#seen"), partition)?;
+        let by_time = store.partition(&format!("{processor}#seen-by-time"), partition)?;
+        if let Some(w) = watermark {
+            for (k, _) in by_time.scan_until(&encode_i64(w.saturating_sub(self.horizon)))? {
+                seen.delete(&k[8..]).await?;
+                by_time.delete(&k).await?;
+            }
+        }
+        if let Some(first_seen) = seen.get(id)? {
+            if timestamp.abs_diff(decode_i64(&first_seen)?) <= self.horizon as u64 {
+                self.deduplicated.fetch_add(1, Ordering::Relaxed);
+                return Ok(true);
+            }
+            by_time
+                .delete(&[first_seen.as_slice(), id].concat())
+                .await?;
+        }
+        seen.put(id, &encode_i64(timestamp)).await?;
+        by_time
+            .put(&[encode_i64(timestamp).as_slice(), id].concat(), &[])
+            .await?;
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn test_is_duplicate() {
+        let store = StateStore::new(None, None, None).unwrap();
+        let dedup = Dedup::new(&conf::DedupConfig {
+            id: DedupId::Key,
+            horizon_ms: 10,
+        })
+        .unwrap();
+        let check = |id: &'static str, ts: i64, watermark: Option<i64>| {
+            let (store, dedup) = (&store, &dedup);
+            async move {
+                dedup
+                    .is_duplicate(store, "d", 0, id.as_bytes(), ts, watermark)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert!(!check("a", 100, None).await);
+        assert!(check("a", 105, None).await);
+        assert!(!check("b", 105, None).await);
+        // Seen again after the horizon, which restarts it.
+        assert!(!check("a", 120, None).await);
+        assert!(check("a", 125, None).await);
+        // b is forgotten once the watermark is past its horizon.
+        assert!(!check("b", 108, Some(116)).await);
+        assert_eq!(dedup.deduplicated(), 2);
+    }
+}
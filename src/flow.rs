@@ -1,149 +1,1360 @@
-wit_bindgen_wasmtime::import!({ paths: ["./wit/record-processor.wit"], async: *});
-use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use anyhow::Context;
-use futures::TryStreamExt;
-use opentelemetry::{metrics::Meter, KeyValue};
+use anyhow::{anyhow, bail, Context};
+use futures::{future::BoxFuture, FutureExt, StreamExt, TryStreamExt};
+use opentelemetry::{
+    metrics::{Counter, Meter},
+    KeyValue,
+};
 use rdkafka::{
-    consumer::StreamConsumer,
-    message::{BorrowedMessage, Headers},
-    Message,
+    consumer::{CommitMode, Consumer},
+    Offset, TopicPartitionList,
+};
+use tracing::{debug, error, info};
+use wasmtime::{component, Config, Engine, Linker, Store};
+use wasmtime_wasi::{
+    preview1::WasiP1Ctx, IoView, ResourceTable, WasiCtx, WasiCtxBuilder, WasiView,
 };
-use record_processor::{FlowRecord, RecordProcessorData};
-use tracing::info;
-use wasi_common::WasiCtx;
-use wasmtime::*;
-use wasmtime_wasi::WasiCtxBuilder;
-
-use crate::sinks::s3::BufferedS3Sink;
 
-use self::record_processor::Status;
+use crate::backpressure::Backpressure;
+use crate::cache::{self, Guest, LoadedModule, ModuleCache};
+use crate::component::dedup::{Deduplicator, DeduplicatorPre};
+use crate::component::join::{JoinProcessor, JoinProcessorPre};
+use crate::component::timed::TimedProcessor;
+use crate::component::windowed::{WindowedProcessor, WindowedProcessorPre};
+use crate::component::{FlowRecord, Processor, ProcessorPre, Status};
+use crate::conf;
+use crate::dedup::Dedup;
+use crate::join::Join;
+use crate::legacy;
+use crate::pipeline::{GuestWorld, RecordCollector, Stage};
+use crate::ratelimit::TokenBucket;
+use crate::record::Record;
+use crate::reload::ModuleReloader;
+use crate::schema::{DecodedView, Decoders};
+use crate::sinks::{SinkRouter, Sinks};
+use crate::sources::rebalance::InFlightRecords;
+use crate::sources::stats::ConsumerStats;
+use crate::sources::{kafka, Input, PARTITION};
+use crate::state::{PartitionState, StateStore};
+use crate::timer::{self, TimerScheduler};
+use crate::watermark::{WatermarkView, Watermarks};
+use crate::window::{ClosedWindow, Windowing};
 
 #[derive(Clone)]
 pub struct FlowContext {
     pub engine: Engine,
     pub linker: Linker<FlowState>,
-    pub module: Module,
-    pub s3_sink: BufferedS3Sink,
+    pub component_linker: component::Linker<FlowState>,
+    pub pipeline: Arc<Vec<Stage>>,
+    pub sinks: Sinks,
+    pub state_store: StateStore,
+    pub watermarks: Watermarks,
+    pub decoders: Arc<Decoders>,
+    /// True when records are read from stdin or written to stdout, which
+    /// guests are then kept away from.
+    pub piped: bool,
 }
 
 pub struct FlowProcessor {
     meter: Meter,
-    pub kafka_consumer: StreamConsumer,
+    rate_limit: Option<Arc<TokenBucket>>,
+    backpressure: Option<Arc<Backpressure>>,
+    pub input: Input,
     pub flow_context: FlowContext,
+    reloaders: Vec<(Stage, ModuleReloader)>,
 }
 
 pub struct FlowState {
     pub wasi: WasiCtx,
-    pub data: RecordProcessorData,
-    pub s3_sink: BufferedS3Sink,
+    pub wasi_p1: WasiP1Ctx,
+    pub table: ResourceTable,
+    pub sinks: SinkRouter,
+    pub emitter: RecordCollector,
+    pub state_store: PartitionState,
+    pub watermark: WatermarkView,
+    pub timers: TimerScheduler,
+    pub decoded: DecodedView,
+}
+
+const BACKPRESSURE_INTERVAL: Duration = Duration::from_millis(100);
+/// How often file positions are saved when there are no state checkpoints.
+const FILE_POSITIONS_INTERVAL: Duration = Duration::from_secs(1);
+/// How often offsets are committed when there are no state checkpoints.
+const OFFSET_COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often timers are checked against the watermarks.
+const TIMER_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates the Wasmtime engine shared by the flow and `wasmflow compile`, so
+/// that precompiled modules match the runtime configuration.
+pub fn create_engine() -> anyhow::Result<Engine> {
+    let mut config = Config::new();
+    config.wasm_multi_memory(true);
+    config.async_support(true);
+    config.wasm_component_model(true);
+    Engine::new(&config).with_context(|| "Could not create a new Wasmtime engine.")
 }
 
 impl FlowProcessor {
     pub fn new(
-        filename: &PathBuf,
+        cfg: &conf::FlowConfig,
         meter: Meter,
-        kafka_consumer: StreamConsumer,
-        s3_sink: BufferedS3Sink,
+        input: Input,
+        sinks: Sinks,
+        state_store: StateStore,
+        watermarks: Watermarks,
     ) -> anyhow::Result<Self> {
-        let mut config = Config::new();
-        config.wasm_multi_memory(true);
-        config.async_support(true);
-        let engine =
-            Engine::new(&config).with_context(|| "Could not create a new Wasmtime engine.")?;
+        let processors = &cfg.processors;
+        if processors.is_empty() {
+            bail!("At least one processor must be configured");
+        }
+        let engine = create_engine()?;
         let mut linker: Linker<FlowState> = Linker::new(&engine);
-        let module = Module::from_file(&engine, filename)
-            .with_context(|| format!("Could not create module: {filename:?}"))?;
-        wasmtime_wasi::add_to_linker(&mut linker, |s| &mut s.wasi)
+        wasmtime_wasi::preview1::add_to_linker_async(&mut linker, |s| &mut s.wasi_p1)
+            .with_context(|| "Failed to add wasi linker.")?;
+        legacy::add_to_linker(&mut linker)?;
+        let mut component_linker: component::Linker<FlowState> = component::Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_async(&mut component_linker)
             .with_context(|| "Failed to add wasi linker.")?;
-        crate::sinks::s3::s3_sink::add_to_linker(&mut linker, |s| &mut s.s3_sink)
+        crate::component::s3_sink::add_to_linker(&mut component_linker, |s| &mut s.sinks)
             .with_context(|| "Failed to add s3_sink")?;
-        let flow_context = FlowContext {
+        crate::component::sinks::add_to_linker(&mut component_linker, |s| &mut s.sinks)
+            .with_context(|| "Failed to add sinks")?;
+        crate::component::record_emitter::add_to_linker(&mut component_linker, |s| &mut s.emitter)
+            .with_context(|| "Failed to add record_emitter")?;
+        crate::component::state_store::add_to_linker(&mut component_linker, |s| &mut s.state_store)
+            .with_context(|| "Failed to add state_store")?;
+        crate::component::watermark::add_to_linker(&mut component_linker, |s| &mut s.watermark)
+            .with_context(|| "Failed to add watermark")?;
+        crate::component::decoded::add_to_linker(&mut component_linker, |s| &mut s.decoded)
+            .with_context(|| "Failed to add decoded")?;
+        crate::component::timers::add_to_linker(&mut component_linker, |s| &mut s.timers)
+            .with_context(|| "Failed to add timers")?;
+        let mut flow_context = FlowContext {
             engine,
             linker,
-            module,
-            s3_sink,
+            component_linker,
+            pipeline: Arc::new(Vec::new()),
+            decoders: Arc::new(Decoders::new(&cfg.sources, &sinks)?),
+            piped: cfg
+                .sources
+                .iter()
+                .any(|s| matches!(s, conf::Source::Stdin { .. }))
+                || cfg
+                    .sinks
+                    .iter()
+                    .any(|s| matches!(s, conf::Sink::Stdout { .. })),
+            sinks,
+            state_store,
+            watermarks,
         };
+        let mut reloaders = Vec::new();
+        let mut pipeline = Vec::new();
+        if let Some(join) = &cfg.join {
+            if join.processor.window.is_some() {
+                bail!("Join processors cannot be windowed");
+            }
+            let kind = Join::new(join, &cfg.sources).with_context(|| "Invalid join")?;
+            pipeline.push(flow_context.build_stage(
+                &join.processor,
+                Some(Arc::new(kind)),
+                &mut reloaders,
+            )?);
+        }
+        for p in processors {
+            pipeline.push(flow_context.build_stage(p, None, &mut reloaders)?);
+        }
+        flow_context.pipeline = Arc::new(pipeline);
+        let rate_limit = cfg
+            .rate_limit
+            .as_ref()
+            .map(|r| TokenBucket::new(r).map(Arc::new))
+            .transpose()
+            .with_context(|| "Invalid flow rate limit")?;
+        let backpressure = cfg
+            .backpressure
+            .as_ref()
+            .map(|b| Backpressure::new(b).map(Arc::new))
+            .transpose()?;
         Ok(Self {
             meter,
-            kafka_consumer,
+            rate_limit,
+            backpressure,
+            input,
             flow_context,
+            reloaders,
         })
     }
 
-    async fn process_msg(fctx: &FlowContext, msg: &BorrowedMessage<'_>) -> anyhow::Result<Status> {
-        let mut headers: Vec<(&str, &[u8])> = Vec::new();
-        if let Some(hdrs) = msg.headers() {
-            for idx in 0..hdrs.count() {
-                if let Some((k, v)) = hdrs.get(idx) {
-                    headers.push((k, v));
-                }
-            }
-        }
-        let frec = FlowRecord {
-            key: msg.key(),
-            value: msg.payload(),
-            headers: &headers,
-            topic: msg.topic(),
-            partition: msg.partition(),
-            offset: msg.offset(),
-            timestamp: msg.timestamp().to_millis().unwrap_or(-1),
-        };
-        let mut fctx = fctx.clone();
-        let flow_state =
-            FlowState::new(fctx.s3_sink).with_context(|| "Error initializing flow state")?;
-        let mut store = Store::new(&fctx.engine, flow_state);
-        let (record_processor, _) = record_processor::RecordProcessor::instantiate(
-            store.as_context_mut(),
-            &fctx.module,
-            &mut fctx.linker,
-            |s| &mut s.data,
-        )
-        .await
-        .with_context(|| "Could not create WASM instance.")?;
-        let status = record_processor
-            .process_record(store.as_context_mut(), frec)
+    async fn process_record(fctx: &FlowContext, mut rec: Record) -> anyhow::Result<Status> {
+        fctx.watermarks.observe(rec.partition, rec.timestamp)?;
+        if let Some(decoder) = fctx.decoders.for_topic(&rec.topic) {
+            if let Err(e) = decoder.decode(&mut rec).await {
+                return Ok(decoder.dead_letter(&fctx.sinks, rec, &e).await);
+            }
+        }
+        fctx.run_stages(&fctx.pipeline, rec.partition, vec![rec])
             .await
-            .with_context(|| "Error invoking WASM function.")?;
-        Ok(status)
     }
 
     pub async fn run(&self) -> anyhow::Result<()> {
-        let record_counter = &self
+        let record_counter = self
             .meter
             .u64_counter("records-processed")
             .with_description("Kafka records processed by topic and partition_id")
             .with_unit(opentelemetry::metrics::Unit::new("count"))
             .init();
-        let fctx = &self.flow_context;
-        self.kafka_consumer
-            .stream()
-            .try_for_each_concurrent(None, |msg| async move {
-                let kv: [KeyValue; 2] = [
-                    KeyValue::new("topic", msg.topic().to_string()),
-                    KeyValue::new("partition_id", msg.partition() as i64),
-                ];
-                record_counter.add(1, &kv);
-                let wasm_status = FlowProcessor::process_msg(fctx, &msg).await;
-                info!(wasm_status=?wasm_status);
-                Ok(())
+        let pipeline = self.flow_context.pipeline.clone();
+        let _active_module = self
+            .meter
+            .u64_value_observer("active-module", move |res| {
+                for stage in pipeline.iter() {
+                    stage.walk(&mut |s| {
+                        if let Ok(m) = s.module.read() {
+                            let kv = [
+                                KeyValue::new("processor", s.name.clone()),
+                                KeyValue::new("module_hash", m.hash.clone()),
+                            ];
+                            res.observe(1, &kv);
+                        }
+                    });
+                }
+            })
+            .with_description("Hash of the WASM module used for new instances by processor")
+            .init();
+        let state_store = self.flow_context.state_store.clone();
+        let _restore_progress = self
+            .meter
+            .u64_value_observer("state-restore-remaining", move |res| {
+                for (partition, progress) in state_store.restore_progress() {
+                    let remaining = progress.total.saturating_sub(progress.restored);
+                    res.observe(
+                        remaining,
+                        &[KeyValue::new("partition_id", partition as i64)],
+                    );
+                }
+            })
+            .with_description("Changelog offsets left to replay by partition_id")
+            .init();
+        let watermarks = self.flow_context.watermarks.clone();
+        let _partition_watermark = self
+            .meter
+            .i64_value_observer("partition-watermark", move |res| {
+                for (partition, watermark) in watermarks.partitions() {
+                    res.observe(
+                        watermark,
+                        &[KeyValue::new("partition_id", partition as i64)],
+                    );
+                }
+            })
+            .with_description("Event-time watermark in epoch milliseconds by partition_id")
+            .init();
+        let watermarks = self.flow_context.watermarks.clone();
+        let _flow_watermark = self
+            .meter
+            .i64_value_observer("flow-watermark", move |res| {
+                if let Some(watermark) = watermarks.flow() {
+                    res.observe(watermark, &[]);
+                }
+            })
+            .with_description("Event-time watermark of the flow in epoch milliseconds")
+            .init();
+        let pipeline = self.flow_context.pipeline.clone();
+        let _deduplicated = self
+            .meter
+            .u64_sum_observer("records-deduplicated", move |res| {
+                for stage in pipeline.iter() {
+                    stage.walk(&mut |s| {
+                        if let Some(dedup) = &s.dedup {
+                            let kv = [KeyValue::new("processor", s.name.clone())];
+                            res.observe(dedup.deduplicated(), &kv);
+                        }
+                    });
+                }
+            })
+            .with_description("Duplicate records dropped by processor")
+            .init();
+        let sinks = self.flow_context.sinks.clone();
+        let _sink_writes = self
+            .meter
+            .u64_sum_observer("sink-writes", move |res| {
+                for (name, stats) in sinks.stats() {
+                    for (status, count) in [("ok", &stats.ok), ("failed", &stats.failed)] {
+                        let kv = [
+                            KeyValue::new("sink", name.to_string()),
+                            KeyValue::new("status", status),
+                        ];
+                        res.observe(count.load(Ordering::Relaxed), &kv);
+                    }
+                }
+            })
+            .with_description("Records written to named sinks by sink and outcome")
+            .init();
+        let (flow_limit, sinks) = (self.rate_limit.clone(), self.flow_context.sinks.clone());
+        let _rate_limit_tokens = self
+            .meter
+            .f64_value_observer("rate-limit-tokens", move |res| {
+                for (limiter, kv) in rate_limiters(flow_limit.as_deref(), &sinks) {
+                    if let Some(tokens) = limiter.available() {
+                        res.observe(tokens, &kv);
+                    }
+                }
+            })
+            .with_description("Tokens available by rate limiter and unit")
+            .init();
+        let (flow_limit, sinks) = (self.rate_limit.clone(), self.flow_context.sinks.clone());
+        let _rate_limit_throttled = self
+            .meter
+            .u64_sum_observer("rate-limit-throttled", move |res| {
+                for (limiter, kv) in rate_limiters(flow_limit.as_deref(), &sinks) {
+                    res.observe(limiter.throttled().as_millis() as u64, &kv);
+                }
+            })
+            .with_description("Time spent waiting on rate limits by limiter and unit")
+            .with_unit(opentelemetry::metrics::Unit::new("ms"))
+            .init();
+        let sinks = self.flow_context.sinks.clone();
+        let _sink_queued = self
+            .meter
+            .u64_value_observer("sink-queued-bytes", move |res| {
+                for (name, queued) in sinks.queued() {
+                    res.observe(queued, &[KeyValue::new("sink", name.to_string())]);
+                }
+            })
+            .with_description("Bytes waiting to be written by sink")
+            .init();
+        let backpressure = self.backpressure.clone();
+        let _in_flight = self
+            .meter
+            .u64_value_observer("in-flight-bytes", move |res| {
+                if let Some(b) = &backpressure {
+                    res.observe(b.in_flight(), &[]);
+                }
+            })
+            .with_description("Bytes of records being processed")
+            .init();
+        let backpressure = self.backpressure.clone();
+        let _paused = self
+            .meter
+            .u64_value_observer("consumer-paused", move |res| {
+                let paused = backpressure.as_ref().is_some_and(|b| b.is_paused());
+                res.observe(paused as u64, &[]);
+            })
+            .with_description("1 while consumption is paused by backpressure")
+            .init();
+        let stats = self.consumer_stats();
+        let _consumer_lag = self
+            .meter
+            .i64_value_observer("consumer-lag", move |res| {
+                for p in stats.latest().partitions {
+                    if let Some(lag) = p.lag {
+                        let kv = [
+                            KeyValue::new("topic", p.topic),
+                            KeyValue::new("partition_id", p.partition as i64),
+                        ];
+                        res.observe(lag, &kv);
+                    }
+                }
+            })
+            .with_description("Records behind the high watermark by topic and partition_id")
+            .init();
+        let stats = self.consumer_stats();
+        let _fetch_queue = self
+            .meter
+            .i64_value_observer("fetch-queue-records", move |res| {
+                for p in stats.latest().partitions {
+                    let kv = [
+                        KeyValue::new("topic", p.topic),
+                        KeyValue::new("partition_id", p.partition as i64),
+                    ];
+                    res.observe(p.fetch_queue_records, &kv);
+                }
+            })
+            .with_description("Records fetched and not yet consumed by topic and partition_id")
+            .init();
+        let stats = self.consumer_stats();
+        let _fetch_queue_bytes = self
+            .meter
+            .u64_value_observer("fetch-queue-bytes", move |res| {
+                for p in stats.latest().partitions {
+                    let kv = [
+                        KeyValue::new("topic", p.topic),
+                        KeyValue::new("partition_id", p.partition as i64),
+                    ];
+                    res.observe(p.fetch_queue_bytes, &kv);
+                }
+            })
+            .with_description("Bytes fetched and not yet consumed by topic and partition_id")
+            .init();
+        let stats = self.consumer_stats();
+        let _rebalances = self
+            .meter
+            .u64_sum_observer("consumer-rebalances", move |res| {
+                res.observe(stats.latest().rebalances, &[]);
+            })
+            .with_description("Consumer group rebalances since the flow started")
+            .init();
+        let stats = self.consumer_stats();
+        let _broker_rtt = self
+            .meter
+            .i64_value_observer("broker-rtt", move |res| {
+                for (broker, rtt) in stats.latest().broker_rtt {
+                    res.observe(rtt, &[KeyValue::new("broker", broker)]);
+                }
+            })
+            .with_description("Average round-trip time to brokers by broker")
+            .with_unit(opentelemetry::metrics::Unit::new("us"))
+            .init();
+        let decoders = self.flow_context.decoders.clone();
+        let _undecodable = self
+            .meter
+            .u64_sum_observer("records-undecodable", move |res| {
+                res.observe(decoders.failed(), &[]);
+            })
+            .with_description("Records whose value could not be decoded with the Schema Registry")
+            .init();
+        let reloads = self
+            .meter
+            .u64_counter("module-reloads")
+            .with_description("WASM module reload attempts by processor and outcome")
+            .with_unit(opentelemetry::metrics::Unit::new("count"))
+            .init();
+        for (stage, reloader) in &self.reloaders {
+            tokio::spawn(reloader.clone().watch(
+                self.flow_context.clone(),
+                stage.clone(),
+                reloads.clone(),
+            ));
+        }
+        let checkpoints = &self
+            .meter
+            .u64_counter("state-checkpoints")
+            .with_description("State checkpoint attempts by outcome")
+            .with_unit(opentelemetry::metrics::Unit::new("count"))
+            .init();
+        // Records hold the gate for reading while they are processed, so a
+        // checkpoint taking it for writing sees no half-processed records.
+        let gate = self
+            .input
+            .kafka()
+            .map(|c| c.context().gate().clone())
+            .unwrap_or_default();
+        let fctx = Arc::new(self.flow_context.clone());
+        let in_flight = self
+            .input
+            .kafka()
+            .map(|c| c.context().in_flight().clone())
+            .unwrap_or_default();
+        let track = |rec: Record| {
+            let tracked = in_flight.track(rec.partition);
+            (rec, tracked)
+        };
+        let records = match &self.input {
+            Input::Kafka(consumer) => kafka::records(consumer).boxed(),
+            Input::File(file) => file.records().map_ok(track).boxed(),
+            Input::Stdin(stdin) => stdin.records().map_ok(track).boxed(),
+            Input::Http(http) => http.records().map_ok(track).boxed(),
+        };
+        // Records piped through stdin are processed one at a time so that
+        // their output keeps their order.
+        let concurrency = match &self.input {
+            Input::Stdin(_) => Some(1),
+            _ => None,
+        };
+        let rate_limit = self.rate_limit.as_deref();
+        // The rate limit is applied as records are pulled from the stream, so
+        // that the consumer falls behind instead of buffering records.
+        let processing = records
+            .and_then(|rec| async move {
+                if let Some(limit) = rate_limit {
+                    limit.acquire(1).await;
+                }
+                Ok(rec)
+            })
+            .try_for_each_concurrent(concurrency, |(rec, tracked)| {
+                // Records are processed on their own tasks and tracked from
+                // the moment they are received, so that a rebalance can wait
+                // for revoked partitions to drain and backpressure counts
+                // records waiting for a checkpoint.
+                let line = self.input.file().map(|f| f.track(&rec));
+                let reply = self.input.http().and_then(|h| h.reply(&rec));
+                let bytes =
+                    rec.key.as_ref().map_or(0, Vec::len) + rec.value.as_ref().map_or(0, Vec::len);
+                let bytes_in_flight = self.backpressure.as_ref().map(|b| b.track(bytes as u64));
+                let (gate, fctx, record_counter) =
+                    (gate.clone(), fctx.clone(), record_counter.clone());
+                let task = tokio::spawn(async move {
+                    let (_tracked, _line, _in_flight) = (tracked, line, bytes_in_flight);
+                    let _processing = gate.read().await;
+                    let (topic, partition, offset) = (rec.topic.clone(), rec.partition, rec.offset);
+                    if !fctx.state_store.should_process(&topic, partition, offset) {
+                        debug!(skipped_checkpointed_offset = offset, partition);
+                        return;
+                    }
+                    let kv: [KeyValue; 2] = [
+                        KeyValue::new("topic", topic.clone()),
+                        KeyValue::new("partition_id", partition as i64),
+                    ];
+                    record_counter.add(1, &kv);
+                    let wasm_status = FlowProcessor::process_record(&fctx, rec).await;
+                    info!(wasm_status=?wasm_status);
+                    if let Err(e) = fctx.state_store.advance(&topic, partition, offset) {
+                        error!(state_offset_error=?e);
+                    }
+                    if let Some(reply) = reply {
+                        let _ = reply.send(matches!(wasm_status, Ok(Status::Ok)));
+                    }
+                });
+                async move {
+                    if let Err(e) = task.await {
+                        error!(record_task_error=?e);
+                    }
+                    Ok(())
+                }
+            });
+        tokio::select! {
+            res = processing => res?,
+            _ = self.run_checkpoints(&gate, checkpoints) => {}
+            _ = self.run_backpressure() => {}
+            _ = self.run_file_positions(&gate) => {}
+            _ = self.run_offset_commits(&gate) => {}
+            _ = Self::run_timers(&gate, &fctx, &in_flight) => {}
+        }
+        // Other sources than Kafka end once read, files unless followed.
+        if self.input.kafka().is_none() {
+            if self.flow_context.sinks.flush(&[PARTITION]).await == Status::Error {
+                bail!("Could not flush sinks");
+            }
+            self.checkpoint().await?;
+        }
+
+        Ok(())
+    }
+
+    fn consumer_stats(&self) -> ConsumerStats {
+        self.input
+            .kafka()
+            .map(|c| c.context().stats().clone())
+            .unwrap_or_default()
+    }
+
+    /// Periodically snapshots state and commits the offsets it reflects.
+    /// Never completes when checkpoints are not configured.
+    async fn run_checkpoints(&self, gate: &tokio::sync::RwLock<()>, checkpoints: &Counter<u64>) {
+        let interval = match self.flow_context.state_store.checkpoint_interval() {
+            Some(i) => i,
+            None => return futures::future::pending().await,
+        };
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let _paused = gate.write().await;
+            let status = match self.checkpoint().await {
+                Ok(()) => "ok",
+                Err(e) => {
+                    error!(checkpoint_error=?e);
+                    "failed"
+                }
+            };
+            checkpoints.add(1, &[KeyValue::new("status", status)]);
+        }
+    }
+
+    /// Commits the offsets of processed records unless they are committed
+    /// with state checkpoints. Never completes for other sources than Kafka.
+    async fn run_offset_commits(&self, gate: &tokio::sync::RwLock<()>) {
+        if self.input.kafka().is_none()
+            || self
+                .flow_context
+                .state_store
+                .checkpoint_interval()
+                .is_some()
+        {
+            return futures::future::pending().await;
+        }
+        let mut ticker = tokio::time::interval(OFFSET_COMMIT_INTERVAL);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            // Records finish out of order, so the offsets are only consistent
+            // once those in flight are done.
+            let _paused = gate.write().await;
+            if let Err(e) = self.checkpoint().await {
+                error!(offset_commit_error=?e);
+            }
+        }
+    }
+
+    /// Saves the positions of a file source unless they are saved with state
+    /// checkpoints. Never completes.
+    async fn run_file_positions(&self, gate: &tokio::sync::RwLock<()>) {
+        let file = match (
+            self.input.file(),
+            self.flow_context.state_store.checkpoint_interval(),
+        ) {
+            (Some(file), None) => file,
+            _ => return futures::future::pending().await,
+        };
+        let mut ticker = tokio::time::interval(FILE_POSITIONS_INTERVAL);
+        loop {
+            ticker.tick().await;
+            // Lines count as processed once their output is flushed, which
+            // takes those in flight to be done.
+            let _paused = gate.write().await;
+            if self.flow_context.sinks.flush(&[PARTITION]).await == Status::Error {
+                error!(file_positions_error = "sink flush failed");
+                continue;
+            }
+            if let Err(e) = file.save() {
+                error!(file_positions_error=?e);
+            }
+        }
+    }
+
+    /// Fires due timers, whether or not their partitions receive records.
+    /// Never completes.
+    async fn run_timers(
+        gate: &Arc<tokio::sync::RwLock<()>>,
+        fctx: &Arc<FlowContext>,
+        in_flight: &InFlightRecords,
+    ) {
+        let mut ticker = tokio::time::interval(TIMER_INTERVAL);
+        loop {
+            ticker.tick().await;
+            // Timers fire on their own task, like records, so that a
+            // rebalance blocking this task can wait for them.
+            let (gate, fctx, in_flight) = (gate.clone(), fctx.clone(), in_flight.clone());
+            let task = tokio::spawn(async move {
+                let _processing = gate.read().await;
+                for (partition, watermark) in fctx.watermarks.timer_watermarks() {
+                    let _tracked = in_flight.track(partition);
+                    if let Err(e) = fctx.fire_timers(&fctx.pipeline, partition, watermark).await {
+                        error!(timer_error=?e, partition);
+                    }
+                }
+            });
+            if let Err(e) = task.await {
+                error!(timer_task_error=?e);
+            }
+        }
+    }
+
+    /// Pauses the assigned partitions while records or sinks hold more bytes
+    /// than configured, and resumes them once those drain. Never completes.
+    async fn run_backpressure(&self) {
+        let backpressure = match &self.backpressure {
+            Some(b) => b,
+            None => return futures::future::pending().await,
+        };
+        let mut ticker = tokio::time::interval(BACKPRESSURE_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let queued = self.flow_context.sinks.queued().map(|(_, q)| q).sum();
+            let res = match backpressure.update(queued) {
+                Some(true) => {
+                    info!(
+                        consumer_paused = true,
+                        in_flight = backpressure.in_flight(),
+                        queued
+                    );
+                    self.pause(true)
+                }
+                Some(false) => {
+                    info!(
+                        consumer_paused = false,
+                        in_flight = backpressure.in_flight(),
+                        queued
+                    );
+                    self.pause(false)
+                }
+                // Partitions assigned while paused start out consuming.
+                None if backpressure.is_paused() => self.pause(true),
+                None => Ok(()),
+            };
+            if let Err(e) = res {
+                error!(backpressure_error=?e);
+            }
+        }
+    }
+
+    fn pause(&self, pause: bool) -> anyhow::Result<()> {
+        let consumer = match &self.input {
+            Input::Kafka(consumer) => consumer,
+            Input::File(file) => {
+                file.pause(pause);
+                return Ok(());
+            }
+            Input::Http(http) => {
+                http.pause(pause);
+                return Ok(());
+            }
+            // Reading stops on its own while records are not taken.
+            Input::Stdin(_) => return Ok(()),
+        };
+        let assignment = consumer.assignment()?;
+        if pause {
+            consumer.pause(&assignment)?;
+        } else {
+            consumer.resume(&assignment)?;
+        }
+        Ok(())
+    }
+
+    /// Checkpoints state and commits the source positions it reflects, the
+    /// offsets of a Kafka source or the positions of a file source. Sinks are
+    /// flushed first, as records only count as processed once their output
+    /// is out of the sink buffers.
+    async fn checkpoint(&self) -> anyhow::Result<()> {
+        let partitions = self.flow_context.state_store.assigned()?;
+        if self.flow_context.sinks.flush(&partitions).await == Status::Error {
+            bail!("Could not flush sinks of partitions {partitions:?}");
+        }
+        let offsets = self.flow_context.state_store.checkpoint().await?;
+        let consumer = match &self.input {
+            Input::Kafka(consumer) => consumer,
+            Input::File(file) => return file.save(),
+            Input::Stdin(_) | Input::Http(_) => return Ok(()),
+        };
+        let mut tpl = TopicPartitionList::new();
+        for (topic, partition, offset) in offsets {
+            tpl.add_partition_offset(&topic, partition, Offset::Offset(offset))?;
+        }
+        if tpl.count() > 0 {
+            consumer
+                .commit(&tpl, CommitMode::Async)
+                .with_context(|| "Could not commit processed offsets")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rate limiters of the flow and its sinks, with the attributes they are
+/// reported under.
+fn rate_limiters<'a>(
+    flow: Option<&'a TokenBucket>,
+    sinks: &'a Sinks,
+) -> Vec<(&'a TokenBucket, [KeyValue; 2])> {
+    let flow = flow.map(|l| {
+        (
+            l,
+            [
+                KeyValue::new("limiter", "flow"),
+                KeyValue::new("unit", "records"),
+            ],
+        )
+    });
+    let sinks = sinks.limiters().map(|(name, unit, l)| {
+        (
+            l,
+            [
+                KeyValue::new("limiter", format!("sink:{name}")),
+                KeyValue::new("unit", unit),
+            ],
+        )
+    });
+    flow.into_iter().chain(sinks).collect()
+}
+
+impl FlowContext {
+    fn build_stage(
+        &self,
+        processor: &conf::Processor,
+        join: Option<Arc<Join>>,
+        reloaders: &mut Vec<(Stage, ModuleReloader)>,
+    ) -> anyhow::Result<Stage> {
+        let module_cache = processor
+            .cache_dir
+            .as_deref()
+            .map(ModuleCache::new)
+            .transpose()?;
+        let module =
+            cache::load_module(&self.engine, &processor.module_path, module_cache.as_ref())?;
+        let dedup = processor
+            .dedup
+            .as_ref()
+            .map(|d| {
+                if !self.state_store.is_persistent() {
+                    bail!("Deduplication needs a state path");
+                }
+                Dedup::new(d).map(Arc::new)
+            })
+            .transpose()
+            .with_context(|| format!("Invalid dedup for {}", processor.name()))?;
+        let world = GuestWorld::of(processor.window.is_some(), join.is_some());
+        self.validate_module(&module.guest, world)
+            .and_then(|_| self.validate_dedup(&module.guest, dedup.as_deref()))
+            .with_context(|| format!("Invalid module: {:?}", processor.module_path))?;
+        info!(processor=%processor.name(), module_path=?processor.module_path, module_hash=%module.hash);
+        let branches = processor
+            .branches
+            .iter()
+            .map(|branch| {
+                branch
+                    .iter()
+                    .map(|p| self.build_stage(p, None, reloaders))
+                    .collect::<anyhow::Result<Vec<_>>>()
             })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let window = processor
+            .window
+            .as_ref()
+            .map(|w| {
+                let late = match &w.late_records {
+                    conf::LateRecords::Drop => None,
+                    conf::LateRecords::SideOutput(pipeline) => Some(
+                        pipeline
+                            .iter()
+                            .map(|p| self.build_stage(p, None, reloaders))
+                            .collect::<anyhow::Result<Vec<_>>>()?,
+                    ),
+                };
+                Windowing::new(w, late).map(Arc::new)
+            })
+            .transpose()
+            .with_context(|| format!("Invalid window for {}", processor.name()))?;
+        let stage = Stage {
+            name: processor.name(),
+            module: Arc::new(RwLock::new(module)),
+            branches,
+            window,
+            dedup,
+            join,
+        };
+        reloaders.push((stage.clone(), ModuleReloader::new(processor, module_cache)));
+        Ok(stage)
+    }
+
+    /// Feeds `records` of `partition` through `stages` in order. Every record
+    /// emitted by a stage is also fed to each of its branches, and the records
+    /// left at the end are routed to sinks. Processing stops at the first
+    /// record a guest reports as an error.
+    fn run_stages<'a>(
+        &'a self,
+        stages: &'a [Stage],
+        partition: i32,
+        mut records: Vec<Record>,
+    ) -> BoxFuture<'a, anyhow::Result<Status>> {
+        async move {
+            for stage in stages {
+                if let Some(dedup) = &stage.dedup {
+                    records = self.deduplicate(stage, dedup, partition, records).await?;
+                }
+                // Windowed stages run even without input, as the watermark may
+                // have closed windows.
+                let (status, emitted) = match (&stage.join, &stage.window) {
+                    (Some(join), _) => self.run_join(stage, join, partition, &records).await?,
+                    (None, Some(window)) => {
+                        self.run_window(stage, window, partition, &records).await?
+                    }
+                    (None, None) => self.run_stage(stage, &records).await?,
+                };
+                if status == Status::Error {
+                    return Ok(status);
+                }
+                for branch in &stage.branches {
+                    if self.run_stages(branch, partition, emitted.clone()).await? == Status::Error {
+                        return Ok(Status::Error);
+                    }
+                }
+                records = emitted;
+            }
+            Ok(self.sinks.route(&records).await)
+        }
+        .boxed()
+    }
+
+    /// Drops the records whose id the stage has already seen. Records without
+    /// an id are kept.
+    async fn deduplicate(
+        &self,
+        stage: &Stage,
+        dedup: &Dedup,
+        partition: i32,
+        records: Vec<Record>,
+    ) -> anyhow::Result<Vec<Record>> {
+        let mut kept = Vec::with_capacity(records.len());
+        for rec in records {
+            let id = match dedup.id {
+                conf::DedupId::Guest => self.invoke_dedup_id(stage, &rec).await?,
+                _ => dedup.record_id(&rec).map(|id| id.to_vec()),
+            };
+            let duplicate = match id {
+                Some(id) => {
+                    let watermark = self.watermarks.get(partition);
+                    dedup
+                        .is_duplicate(
+                            &self.state_store,
+                            &stage.name,
+                            partition,
+                            &id,
+                            rec.timestamp,
+                            watermark,
+                        )
+                        .await?
+                }
+                None => false,
+            };
+            if duplicate {
+                debug!(processor=%stage.name, duplicate_offset=rec.offset, partition);
+            } else {
+                kept.push(rec);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// Runs `records` through the stage.
+    async fn run_stage(
+        &self,
+        stage: &Stage,
+        records: &[Record],
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let mut emitted = Vec::new();
+        for rec in records {
+            let (status, mut out) = self.invoke(stage, rec).await?;
+            if status == Status::Error {
+                return Ok((status, emitted));
+            }
+            emitted.append(&mut out);
+        }
+        Ok((Status::Ok, emitted))
+    }
+
+    /// Fires the timers of `stages` that `watermark` has reached on
+    /// `partition`. The records a timer emits go through the stage's branches
+    /// and the stages after it, like those of a record. A timer is removed
+    /// once its callback has run, so one whose guest traps fires again.
+    fn fire_timers<'a>(
+        &'a self,
+        stages: &'a [Stage],
+        partition: i32,
+        watermark: i64,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        async move {
+            for (i, stage) in stages.iter().enumerate() {
+                let late = stage.window.iter().filter_map(|w| w.late.as_ref());
+                for branch in stage.branches.iter().chain(late) {
+                    self.fire_timers(branch, partition, watermark).await?;
+                }
+                // Only processor components can schedule timers.
+                if stage.world() != GuestWorld::Processor
+                    || matches!(stage.active_module()?.guest, Guest::Core(_))
+                {
+                    continue;
+                }
+                let timers = self.timer_scheduler(stage, partition)?;
+                for (at, key) in timers.due(watermark)? {
+                    let (status, emitted) =
+                        match self.invoke_timer(stage, partition, at, key.clone()).await {
+                            Ok(res) => res,
+                            Err(e) => {
+                                error!(processor=%stage.name, timer_error=?e);
+                                continue;
+                            }
+                        };
+                    timers.remove(at, &key).await?;
+                    let status = match status {
+                        Status::Ok => self.run_emitted(stages, i, partition, emitted).await?,
+                        status => status,
+                    };
+                    info!(processor=%stage.name, timer_status=?status, partition);
+                }
+            }
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Feeds records emitted by `stages[i]` to its branches and to the stages
+    /// after it, as [`run_stages`](Self::run_stages) does.
+    async fn run_emitted(
+        &self,
+        stages: &[Stage],
+        i: usize,
+        partition: i32,
+        emitted: Vec<Record>,
+    ) -> anyhow::Result<Status> {
+        for branch in &stages[i].branches {
+            if self.run_stages(branch, partition, emitted.clone()).await? == Status::Error {
+                return Ok(Status::Error);
+            }
+        }
+        self.run_stages(&stages[i + 1..], partition, emitted).await
+    }
+
+    /// Buffers `records` into the stage's windows and passes the windows the
+    /// watermark closed to the guest. Late records go to the late-record
+    /// pipeline, if any.
+    async fn run_window(
+        &self,
+        stage: &Stage,
+        window: &Windowing,
+        partition: i32,
+        records: &[Record],
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let watermark = self.watermarks.get(partition);
+        let (late, closed) = window
+            .update(
+                &self.state_store,
+                &stage.name,
+                partition,
+                records,
+                watermark,
+            )
             .await?;
+        if !late.is_empty() {
+            match &window.late {
+                Some(pipeline) => {
+                    if self.run_stages(pipeline, partition, late).await? == Status::Error {
+                        return Ok((Status::Error, Vec::new()));
+                    }
+                }
+                None => debug!(processor=%stage.name, late_records_dropped=late.len()),
+            }
+        }
+        let mut emitted = Vec::new();
+        for c in closed {
+            let (status, mut out) = self.invoke_window_close(stage, partition, c).await?;
+            if status == Status::Error {
+                return Ok((status, emitted));
+            }
+            emitted.append(&mut out);
+        }
+        Ok((Status::Ok, emitted))
+    }
 
+    /// Buffers `records` in the join and passes every pair they complete to
+    /// the guest.
+    async fn run_join(
+        &self,
+        stage: &Stage,
+        join: &Join,
+        partition: i32,
+        records: &[Record],
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let mut emitted = Vec::new();
+        for rec in records {
+            let watermark = self.watermarks.get(partition);
+            for (left, right) in join
+                .update(&self.state_store, &stage.name, rec, watermark)
+                .await?
+            {
+                let (status, mut out) = self.invoke_join(stage, &left, &right).await?;
+                if status == Status::Error {
+                    return Ok((status, emitted));
+                }
+                emitted.append(&mut out);
+            }
+        }
+        Ok((Status::Ok, emitted))
+    }
+
+    /// Runs a single record through a fresh instance of the stage's module,
+    /// returning the guest status and the records it emitted.
+    async fn invoke(&self, stage: &Stage, rec: &Record) -> anyhow::Result<(Status, Vec<Record>)> {
+        // Records already in flight keep the module they started with even if
+        // a reload swaps it in the meantime.
+        let guest = stage.active_module()?.guest;
+        let mut store = self.new_store(stage, rec)?;
+        let status = match &guest {
+            Guest::Component(component) => {
+                let processor =
+                    Processor::instantiate_async(&mut store, component, &self.component_linker)
+                        .await
+                        .with_context(|| {
+                            format!("Could not create WASM instance for {}.", stage.name)
+                        })?;
+                processor
+                    .call_process_record(&mut store, &FlowRecord::from(rec))
+                    .await
+            }
+            Guest::Core(module) => {
+                let instance = self
+                    .linker
+                    .instantiate_async(&mut store, module)
+                    .await
+                    .with_context(|| {
+                        format!("Could not create WASM instance for {}.", stage.name)
+                    })?;
+                let processor = legacy::RecordProcessor::new(&mut store, &instance)?;
+                processor.process_record(&mut store, rec).await
+            }
+        }
+        .with_context(|| format!("Error invoking WASM function in {}.", stage.name))?;
+        Ok((
+            status,
+            std::mem::take(&mut store.data_mut().emitter.records),
+        ))
+    }
+
+    /// Passes a closed window to the `on-window-close` export of the stage's
+    /// component, returning the guest status and the records it emitted.
+    async fn invoke_window_close(
+        &self,
+        stage: &Stage,
+        partition: i32,
+        closed: ClosedWindow,
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let component = match stage.active_module()?.guest {
+            Guest::Component(component) => component,
+            Guest::Core(_) => bail!("Windowed processor {} is not a component", stage.name),
+        };
+        let mut store = self.new_store(stage, &closed.record(partition))?;
+        let processor =
+            WindowedProcessor::instantiate_async(&mut store, &component, &self.component_linker)
+                .await
+                .with_context(|| format!("Could not create WASM instance for {}.", stage.name))?;
+        let status = processor
+            .call_on_window_close(&mut store, &closed.key, closed.window, &closed.state)
+            .await
+            .with_context(|| format!("Error invoking WASM function in {}.", stage.name))?;
+        Ok((
+            status,
+            std::mem::take(&mut store.data_mut().emitter.records),
+        ))
+    }
+
+    /// Passes a joined pair to the `process-join` export of the stage's
+    /// component, returning the guest status and the records it emitted.
+    async fn invoke_join(
+        &self,
+        stage: &Stage,
+        left: &Record,
+        right: &Record,
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let component = match stage.active_module()?.guest {
+            Guest::Component(component) => component,
+            Guest::Core(_) => bail!("Join processor {} is not a component", stage.name),
+        };
+        let mut store = self.new_store(stage, left)?;
+        let processor =
+            JoinProcessor::instantiate_async(&mut store, &component, &self.component_linker)
+                .await
+                .with_context(|| format!("Could not create WASM instance for {}.", stage.name))?;
+        let status = processor
+            .call_process_join(
+                &mut store,
+                &FlowRecord::from(left),
+                &FlowRecord::from(right),
+            )
+            .await
+            .with_context(|| format!("Error invoking WASM function in {}.", stage.name))?;
+        Ok((
+            status,
+            std::mem::take(&mut store.data_mut().emitter.records),
+        ))
+    }
+
+    /// Calls the `dedup-id` export of the stage's component for `rec`.
+    async fn invoke_dedup_id(
+        &self,
+        stage: &Stage,
+        rec: &Record,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let component = match stage.active_module()?.guest {
+            Guest::Component(component) => component,
+            Guest::Core(_) => bail!("Processor {} is not a component", stage.name),
+        };
+        let mut store = self.new_store(stage, rec)?;
+        let deduplicator =
+            Deduplicator::instantiate_async(&mut store, &component, &self.component_linker)
+                .await
+                .with_context(|| format!("{} does not export dedup-id.", stage.name))?;
+        deduplicator
+            .call_dedup_id(&mut store, &FlowRecord::from(rec))
+            .await
+            .with_context(|| format!("Error invoking WASM function in {}.", stage.name))
+    }
+
+    /// Calls the `on-timer` export of the stage's component for a timer due
+    /// at `at`. Emitted records take the timer key and time.
+    async fn invoke_timer(
+        &self,
+        stage: &Stage,
+        partition: i32,
+        at: i64,
+        key: Vec<u8>,
+    ) -> anyhow::Result<(Status, Vec<Record>)> {
+        let component = match stage.active_module()?.guest {
+            Guest::Component(component) => component,
+            Guest::Core(_) => bail!("Processor {} with timers is not a component", stage.name),
+        };
+        let rec = Record {
+            key: Some(key),
+            partition,
+            timestamp: at,
+            ..Default::default()
+        };
+        let mut store = self.new_store(stage, &rec)?;
+        let processor =
+            TimedProcessor::instantiate_async(&mut store, &component, &self.component_linker)
+                .await
+                .with_context(|| format!("{} does not export on-timer.", stage.name))?;
+        let status = processor
+            .call_on_timer(&mut store, rec.key.as_deref().unwrap_or_default())
+            .await
+            .with_context(|| format!("Error invoking WASM function in {}.", stage.name))?;
+        Ok((
+            status,
+            std::mem::take(&mut store.data_mut().emitter.records),
+        ))
+    }
+
+    fn timer_scheduler(&self, stage: &Stage, partition: i32) -> anyhow::Result<TimerScheduler> {
+        Ok(TimerScheduler {
+            state: self
+                .state_store
+                .partition(&timer::tree_name(&stage.name), partition)?,
+        })
+    }
+
+    /// Creates a store for one guest invocation with `rec` as its input.
+    fn new_store(&self, stage: &Stage, rec: &Record) -> anyhow::Result<Store<FlowState>> {
+        let state_store = self.state_store.partition(&stage.name, rec.partition)?;
+        let watermark = WatermarkView {
+            watermarks: self.watermarks.clone(),
+            partition: rec.partition,
+        };
+        let timers = self.timer_scheduler(stage, rec.partition)?;
+        let sinks = SinkRouter {
+            sinks: self.sinks.clone(),
+            partition: rec.partition,
+        };
+        let mut flow_state = FlowState::new(sinks, state_store, watermark, timers, self.piped)
+            .with_context(|| "Error initializing flow state")?;
+        flow_state.emitter = RecordCollector::new(rec);
+        flow_state.decoded = DecodedView {
+            decoded: rec.decoded.clone(),
+        };
+        Ok(Store::new(&self.engine, flow_state))
+    }
+
+    /// Checks that `guest` exports `world` and that all its imports can be
+    /// satisfied by the linker. Only record processors may be core modules.
+    pub fn validate_module(&self, guest: &Guest, world: GuestWorld) -> anyhow::Result<()> {
+        match guest {
+            Guest::Component(component) if world == GuestWorld::Windowed => {
+                let pre = self
+                    .component_linker
+                    .instantiate_pre(component)
+                    .with_context(|| "Component imports cannot be satisfied")?;
+                WindowedProcessorPre::new(pre).with_context(|| {
+                    "Component does not export the wasmflow:processor/windowed-processor world"
+                })?;
+            }
+            Guest::Component(component) if world == GuestWorld::Join => {
+                let pre = self
+                    .component_linker
+                    .instantiate_pre(component)
+                    .with_context(|| "Component imports cannot be satisfied")?;
+                JoinProcessorPre::new(pre).with_context(|| {
+                    "Component does not export the wasmflow:processor/join-processor world"
+                })?;
+            }
+            Guest::Core(_) if world != GuestWorld::Processor => {
+                bail!("Windowed and join processors must be components")
+            }
+            Guest::Component(component) => {
+                let pre = self
+                    .component_linker
+                    .instantiate_pre(component)
+                    .with_context(|| "Component imports cannot be satisfied")?;
+                ProcessorPre::new(pre)
+                    .with_context(|| "Component does not export the wasmflow:processor world")?;
+            }
+            Guest::Core(module) => {
+                for export in legacy::REQUIRED_EXPORTS {
+                    if module.get_export(export).is_none() {
+                        bail!("Module does not export `{export}`");
+                    }
+                }
+                self.linker
+                    .instantiate_pre(module)
+                    .with_context(|| "Module imports cannot be satisfied")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `guest` exports `dedup-id` if `dedup` needs it.
+    fn validate_dedup(&self, guest: &Guest, dedup: Option<&Dedup>) -> anyhow::Result<()> {
+        if !dedup.is_some_and(|d| matches!(d.id, conf::DedupId::Guest)) {
+            return Ok(());
+        }
+        match guest {
+            Guest::Component(component) => {
+                let pre = self
+                    .component_linker
+                    .instantiate_pre(component)
+                    .with_context(|| "Component imports cannot be satisfied")?;
+                DeduplicatorPre::new(pre).with_context(|| {
+                    "Component does not export the wasmflow:processor/deduplicator world"
+                })?;
+                Ok(())
+            }
+            Guest::Core(_) => bail!("Guest-computed dedup ids need a component"),
+        }
+    }
+
+    /// Validates `candidate` and makes it the module used for new instances
+    /// of `stage`.
+    pub fn swap_module(&self, stage: &Stage, candidate: LoadedModule) -> anyhow::Result<()> {
+        self.validate_module(&candidate.guest, stage.world())?;
+        self.validate_dedup(&candidate.guest, stage.dedup.as_deref())?;
+        let mut active = stage
+            .module
+            .write()
+            .map_err(|_| anyhow!("Active module lock poisoned"))?;
+        *active = candidate;
         Ok(())
     }
 }
 
 impl FlowState {
-    pub fn new(s3_writer: BufferedS3Sink) -> anyhow::Result<Self> {
+    pub fn new(
+        sinks: SinkRouter,
+        state_store: PartitionState,
+        watermark: WatermarkView,
+        timers: TimerScheduler,
+        piped: bool,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
-            wasi: WasiCtxBuilder::new()
-                .inherit_stdio()
-                //.inherit_args()
-                // .with_context(|| "Could not initialize WASI")?
-                .build(),
-            data: RecordProcessorData {},
-            s3_sink: s3_writer,
+            wasi: wasi_builder(piped).build(),
+            wasi_p1: wasi_builder(piped).build_p1(),
+            table: ResourceTable::new(),
+            sinks,
+            emitter: RecordCollector::default(),
+            state_store,
+            watermark,
+            timers,
+            decoded: DecodedView::default(),
         })
     }
 }
+
+/// Guests share the stdio of the flow, except for stdin and stdout when they
+/// carry records, in which case guests write to stderr instead.
+fn wasi_builder(piped: bool) -> WasiCtxBuilder {
+    let mut builder = WasiCtxBuilder::new();
+    if piped {
+        builder.stdout(wasmtime_wasi::stderr()).inherit_stderr();
+    } else {
+        builder.inherit_stdio();
+    }
+    builder
+}
+
+impl IoView for FlowState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for FlowState {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
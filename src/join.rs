@@ -0,0 +1,266 @@
+use anyhow::{bail, Context};
+use tracing::debug;
+
+use crate::conf::{self, JoinKind};
+use crate::record::Record;
+use crate::state::{encode_i64, PartitionState, StateStore};
+
+/// Joins the records of two co-partitioned sources by key. Buffered records
+/// live in the state store next to the guest state of the join processor, so
+/// they are checkpointed and restored with it.
+///
+/// Table joins keep the latest right record of each key in a
+/// `{processor}#table` tree. Window joins buffer both sides in
+/// `{processor}#left` and `{processor}#right` trees keyed by record key,
+/// timestamp and offset, and index them by timestamp in `{processor}#expiry`
+/// so that entries the watermark has moved past can be dropped.
+pub struct Join {
+    kind: JoinKind,
+    left_topic: String,
+    right_topic: String,
+    // Records of the same partition are processed concurrently.
+    lock: tokio::sync::Mutex<()>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+impl Join {
+    /// Joins the first two of `sources`, which are the left and right sides.
+    pub fn new(cfg: &conf::JoinConfig, sources: &[conf::Source]) -> anyhow::Result<Self> {
+        let (left, right) = match sources {
+            [left, right, ..] => (left.topic(), right.topic()),
+            _ => bail!("Joins need two sources"),
+        };
+        let (left, right) = match (left, right) {
+            (Some(left), Some(right)) => (left, right),
+            _ => bail!("Joined sources must each subscribe to a single topic by name"),
+        };
+        if left == right {
+            bail!("Cannot join topic {left} with itself");
+        }
+        if let JoinKind::Window { within_ms } = cfg.kind {
+            i64::try_from(within_ms).with_context(|| "within_ms is out of range")?;
+        }
+        Ok(Self {
+            kind: cfg.kind,
+            left_topic: left.to_string(),
+            right_topic: right.to_string(),
+            lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Buffers `rec` and returns the left and right record pairs it completes.
+    /// Window buffers are first trimmed to what can still match at
+    /// `watermark`.
+    pub async fn update(
+        &self,
+        store: &StateStore,
+        processor: &str,
+        rec: &Record,
+        watermark: Option<i64>,
+    ) -> anyhow::Result<Vec<(Record, Record)>> {
+        let side = if rec.topic == self.left_topic {
+            Side::Left
+        } else if rec.topic == self.right_topic {
+            Side::Right
+        } else {
+            bail!("Topic {} is not part of the join", rec.topic);
+        };
+        let key = match &rec.key {
+            Some(key) => key,
+            None => {
+                debug!(processor, unkeyed_record_offset = rec.offset);
+                return Ok(Vec::new());
+            }
+        };
+        let _guard = self.lock.lock().await;
+        match self.kind {
+            JoinKind::Table => {
+                let table = store.partition(&format!("{processor}#table"), rec.partition)?;
+                match side {
+                    Side::Left => Ok(table
+                        .get(key)?
+                        .map(|right| Record::decode(&right))
+                        .transpose()?
+                        .map(|right| vec![(rec.clone(), right)])
+                        .unwrap_or_default()),
+                    Side::Right if rec.value.is_none() => {
+                        table.delete(key).await?;
+                        Ok(Vec::new())
+                    }
+                    Side::Right => {
+                        table.put(key, &rec.encode()).await?;
+                        Ok(Vec::new())
+                    }
+                }
+            }
+            JoinKind::Window { within_ms } => {
+                let within = within_ms as i64;
+                let buffers = Buffers::new(store, processor, rec.partition)?;
+                if let Some(w) = watermark {
+                    buffers.expire(w.saturating_sub(within)).await?;
+                }
+                buffers.insert(side, key, rec, within).await
+            }
+        }
+    }
+}
+
+struct Buffers {
+    left: PartitionState,
+    right: PartitionState,
+    expiry: PartitionState,
+}
+
+impl Buffers {
+    fn new(store: &StateStore, processor: &str, partition: i32) -> anyhow::Result<Self> {
+        Ok(Self {
+            left: store.partition(&format!("{processor}#left"), partition)?,
+            right: store.partition(&format!("{processor}#right"), partition)?,
+            expiry: store.partition(&format!("{processor}#expiry"), partition)?,
+        })
+    }
+
+    fn side(&self, side: Side) -> &PartitionState {
+        match side {
+            Side::Left => &self.left,
+            Side::Right => &self.right,
+        }
+    }
+
+    /// Buffers `rec` on `side` and pairs it with the records buffered on the
+    /// other side whose timestamps are at most `within` away.
+    async fn insert(
+        &self,
+        side: Side,
+        key: &[u8],
+        rec: &Record,
+        within: i64,
+    ) -> anyhow::Result<Vec<(Record, Record)>> {
+        let prefix = key_prefix(key);
+        let other = match side {
+            Side::Left => Side::Right,
+            Side::Right => Side::Left,
+        };
+        let mut pairs = Vec::new();
+        for (_, v) in self.side(other).scan_prefix(&prefix)? {
+            let buffered = Record::decode(&v)?;
+            if buffered.timestamp.abs_diff(rec.timestamp) > within as u64 {
+                continue;
+            }
+            pairs.push(match side {
+                Side::Left => (rec.clone(), buffered),
+                Side::Right => (buffered, rec.clone()),
+            });
+        }
+        let buffer_key = [prefix, encode_i64(rec.timestamp), encode_i64(rec.offset)].concat();
+        self.side(side).put(&buffer_key, &rec.encode()).await?;
+        let index_key = [encode_i64(rec.timestamp), vec![side as u8], buffer_key].concat();
+        self.expiry.put(&index_key, &[]).await?;
+        Ok(pairs)
+    }
+
+    /// Drops the records of both sides older than `until`.
+    async fn expire(&self, until: i64) -> anyhow::Result<()> {
+        for (k, _) in self.expiry.scan_until(&encode_i64(until))? {
+            if k.len() < 9 {
+                bail!("Invalid join expiry entry");
+            }
+            let side = if k[8] == Side::Left as u8 {
+                Side::Left
+            } else {
+                Side::Right
+            };
+            self.side(side).delete(&k[9..]).await?;
+            self.expiry.delete(&k).await?;
+        }
+        Ok(())
+    }
+}
+
+fn key_prefix(key: &[u8]) -> Vec<u8> {
+    [&(key.len() as u32).to_be_bytes(), key].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(topic: &str, key: &str, value: Option<&str>, offset: i64, timestamp: i64) -> Record {
+        Record {
+            key: Some(key.as_bytes().to_vec()),
+            value: value.map(|v| v.as_bytes().to_vec()),
+            topic: topic.to_string(),
+            offset,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn join(kind: JoinKind) -> Join {
+        Join {
+            kind,
+            left_topic: "orders".to_string(),
+            right_topic: "customers".to_string(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn values(pairs: &[(Record, Record)]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        pairs
+            .iter()
+            .map(|(l, r)| (l.value.clone().unwrap(), r.value.clone().unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_joins() {
+        let store = StateStore::new(None, None, None).unwrap();
+        let table = join(JoinKind::Table);
+        let order = rec("orders", "c1", Some("o1"), 0, 10);
+        assert!(table
+            .update(&store, "t", &order, None)
+            .await
+            .unwrap()
+            .is_empty());
+        let customer = rec("customers", "c1", Some("alice"), 0, 5);
+        table.update(&store, "t", &customer, None).await.unwrap();
+        let pairs = table.update(&store, "t", &order, None).await.unwrap();
+        assert_eq!(values(&pairs), vec![(b"o1".to_vec(), b"alice".to_vec())]);
+        let tombstone = rec("customers", "c1", None, 1, 6);
+        table.update(&store, "t", &tombstone, None).await.unwrap();
+        assert!(table
+            .update(&store, "t", &order, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let window = join(JoinKind::Window { within_ms: 5 });
+        let left = rec("orders", "k", Some("l1"), 0, 100);
+        assert!(window
+            .update(&store, "w", &left, None)
+            .await
+            .unwrap()
+            .is_empty());
+        let far = rec("customers", "k", Some("r1"), 0, 110);
+        assert!(window
+            .update(&store, "w", &far, None)
+            .await
+            .unwrap()
+            .is_empty());
+        let near = rec("customers", "k", Some("r2"), 1, 103);
+        let pairs = window.update(&store, "w", &near, Some(103)).await.unwrap();
+        assert_eq!(values(&pairs), vec![(b"l1".to_vec(), b"r2".to_vec())]);
+        // l1 is dropped once the watermark is more than 5ms past it.
+        let right = rec("customers", "k", Some("r3"), 2, 104);
+        let pairs = window.update(&store, "w", &right, Some(108)).await.unwrap();
+        assert!(pairs.is_empty());
+        let left = rec("orders", "k", Some("l2"), 1, 112);
+        let pairs = window.update(&store, "w", &left, Some(108)).await.unwrap();
+        assert_eq!(values(&pairs), vec![(b"l2".to_vec(), b"r1".to_vec())]);
+    }
+}
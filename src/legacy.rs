@@ -0,0 +1,320 @@
+//! Compatibility shim for guests built as core modules against
+//! `wit/record-processor.wit` and `wit/s3-sink.wit` with the 2022
+//! wit-bindgen. Implements the canonical ABI used by those bindings, and
+//! offers `record-emitter.emit` taking an `emitted-record` flattened the same
+//! way.
+
+use anyhow::{anyhow, Context};
+use wasmtime::{Caller, Instance, Linker, Memory, Store, TypedFunc};
+
+use crate::component::Status;
+use crate::flow::FlowState;
+use crate::record::Record;
+
+/// Exports every legacy guest must provide.
+pub const REQUIRED_EXPORTS: [&str; 3] = ["memory", "canonical_abi_realloc", "process-record"];
+
+type ProcessRecordParams = (
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i32,
+    i64,
+    i64,
+);
+
+type EmitParams = (i32, i32, i32, i32, i32, i32, i32, i32);
+
+// Size of a `tuple<string, list<u8>>` element: two (pointer, length) pairs.
+const HEADER_SIZE: i32 = 16;
+
+pub struct RecordProcessor {
+    canonical_abi_realloc: TypedFunc<(i32, i32, i32, i32), i32>,
+    memory: Memory,
+    process_record: TypedFunc<ProcessRecordParams, i32>,
+}
+
+impl RecordProcessor {
+    pub fn new(store: &mut Store<FlowState>, instance: &Instance) -> anyhow::Result<Self> {
+        let canonical_abi_realloc =
+            instance.get_typed_func(&mut *store, "canonical_abi_realloc")?;
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .ok_or_else(|| anyhow!("`memory` export not a memory"))?;
+        let process_record = instance.get_typed_func(&mut *store, "process-record")?;
+        Ok(Self {
+            canonical_abi_realloc,
+            memory,
+            process_record,
+        })
+    }
+
+    pub async fn process_record(
+        &self,
+        store: &mut Store<FlowState>,
+        rec: &Record,
+    ) -> anyhow::Result<Status> {
+        let (key_tag, key_ptr, key_len) = self.store_option(store, rec.key.as_deref()).await?;
+        let (value_tag, value_ptr, value_len) =
+            self.store_option(store, rec.value.as_deref()).await?;
+        let headers_len = rec.headers.len() as i32;
+        let headers_ptr = self
+            .canonical_abi_realloc
+            .call_async(&mut *store, (0, 0, 4, headers_len * HEADER_SIZE))
+            .await?;
+        for (i, (k, v)) in rec.headers.iter().enumerate() {
+            let k_ptr = self.store_bytes(store, k.as_bytes()).await?;
+            let v_ptr = self.store_bytes(store, v).await?;
+            let mut elem = Vec::with_capacity(HEADER_SIZE as usize);
+            for field in [k_ptr, k.len() as i32, v_ptr, v.len() as i32] {
+                elem.extend_from_slice(&field.to_le_bytes());
+            }
+            let base = headers_ptr + (i as i32) * HEADER_SIZE;
+            self.memory.write(&mut *store, base as usize, &elem)?;
+        }
+        let topic_ptr = self.store_bytes(store, rec.topic.as_bytes()).await?;
+        let status = self
+            .process_record
+            .call_async(
+                &mut *store,
+                (
+                    key_tag,
+                    key_ptr,
+                    key_len,
+                    value_tag,
+                    value_ptr,
+                    value_len,
+                    headers_ptr,
+                    headers_len,
+                    topic_ptr,
+                    rec.topic.len() as i32,
+                    rec.partition,
+                    rec.offset,
+                    rec.timestamp,
+                ),
+            )
+            .await?;
+        match status {
+            0 => Ok(Status::Ok),
+            1 => Ok(Status::Error),
+            _ => Err(anyhow!("Invalid variant for Status: {status}")),
+        }
+    }
+
+    async fn store_bytes(&self, store: &mut Store<FlowState>, bytes: &[u8]) -> anyhow::Result<i32> {
+        let ptr = self
+            .canonical_abi_realloc
+            .call_async(&mut *store, (0, 0, 1, bytes.len() as i32))
+            .await?;
+        self.memory.write(&mut *store, ptr as usize, bytes)?;
+        Ok(ptr)
+    }
+
+    async fn store_option(
+        &self,
+        store: &mut Store<FlowState>,
+        bytes: Option<&[u8]>,
+    ) -> anyhow::Result<(i32, i32, i32)> {
+        match bytes {
+            Some(b) => Ok((1, self.store_bytes(store, b).await?, b.len() as i32)),
+            None => Ok((0, 0, 0)),
+        }
+    }
+}
+
+/// Adds the host functions imported by legacy guests.
+pub fn add_to_linker(linker: &mut Linker<FlowState>) -> anyhow::Result<()> {
+    linker
+        .func_wrap_async(
+            "s3-sink",
+            "write",
+            |mut caller: Caller<'_, FlowState>, (partition, ptr, len): (i32, i32, i32)| {
+                Box::new(async move {
+                    let body = slice(memory(&mut caller)?.data(&caller), ptr, len)?.to_vec();
+                    let status = caller
+                        .data_mut()
+                        .sinks
+                        .sinks
+                        .write_s3(partition, &body)
+                        .await;
+                    Ok(status as i32)
+                })
+            },
+        )
+        .with_context(|| "Failed to add legacy s3-sink")?;
+    linker
+        .func_wrap_async(
+            "record-emitter",
+            "emit",
+            |mut caller: Caller<'_, FlowState>, params: EmitParams| {
+                Box::new(async move {
+                    let (key_tag, key_ptr, key_len, value_tag, value_ptr, value_len, ptr, len) =
+                        params;
+                    let data = memory(&mut caller)?.data(&caller);
+                    let key = option(data, key_tag, key_ptr, key_len)?;
+                    let value = option(data, value_tag, value_ptr, value_len)?;
+                    let mut headers = Vec::with_capacity(len as usize);
+                    for i in 0..len {
+                        let base = ptr + i * HEADER_SIZE;
+                        let k = slice(data, read_i32(data, base)?, read_i32(data, base + 4)?)?;
+                        let v = slice(data, read_i32(data, base + 8)?, read_i32(data, base + 12)?)?;
+                        headers.push((std::str::from_utf8(k)?.to_string(), v.to_vec()));
+                    }
+                    caller.data_mut().emitter.push(key, value, headers);
+                    Ok(())
+                })
+            },
+        )
+        .with_context(|| "Failed to add legacy record-emitter")?;
+    Ok(())
+}
+
+fn memory(caller: &mut Caller<'_, FlowState>) -> anyhow::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|e| e.into_memory())
+        .ok_or_else(|| anyhow!("`memory` export not a memory"))
+}
+
+fn slice(data: &[u8], ptr: i32, len: i32) -> anyhow::Result<&[u8]> {
+    let start = ptr as u32 as usize;
+    start
+        .checked_add(len as u32 as usize)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| anyhow!("Out of bounds guest memory access"))
+}
+
+fn read_i32(data: &[u8], ptr: i32) -> anyhow::Result<i32> {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(slice(data, ptr, 4)?);
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn option(data: &[u8], tag: i32, ptr: i32, len: i32) -> anyhow::Result<Option<Vec<u8>>> {
+    match tag {
+        0 => Ok(None),
+        1 => Ok(Some(slice(data, ptr, len)?.to_vec())),
+        _ => Err(anyhow!("Invalid variant for option: {tag}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{self, FlowConfig, LineFormat};
+    use crate::flow::{create_engine, FlowProcessor};
+    use crate::pipeline::RecordCollector;
+    use crate::sinks::{SinkRouter, Sinks};
+    use crate::sources::stdin::StdinSource;
+    use crate::sources::Input;
+    use crate::state::StateStore;
+    use crate::timer::TimerScheduler;
+    use crate::watermark::{WatermarkView, Watermarks};
+    use wasmtime::Module;
+
+    /// Emits its input with the key and value swapped and the same headers,
+    /// as bindings of the 2022 wit-bindgen would.
+    const SWAP: &str = r#"(module
+        (import "record-emitter" "emit"
+            (func $emit (param i32 i32 i32 i32 i32 i32 i32 i32)))
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "canonical_abi_realloc")
+            (param i32 i32 i32 i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get 3)))
+            (local.get $ptr))
+        (func (export "process-record")
+            (param $key_tag i32) (param $key_ptr i32) (param $key_len i32)
+            (param $value_tag i32) (param $value_ptr i32) (param $value_len i32)
+            (param $headers_ptr i32) (param $headers_len i32)
+            (param i32 i32 i32 i64 i64) (result i32)
+            (call $emit
+                (local.get $value_tag) (local.get $value_ptr) (local.get $value_len)
+                (local.get $key_tag) (local.get $key_ptr) (local.get $key_len)
+                (local.get $headers_ptr) (local.get $headers_len))
+            (i32.const 0)))"#;
+
+    #[tokio::test]
+    async fn test_process_record() {
+        let engine = create_engine().unwrap();
+        let mut linker = Linker::new(&engine);
+        add_to_linker(&mut linker).unwrap();
+        let module = Module::new(&engine, SWAP).unwrap();
+        let state_store = StateStore::new(None, None, None).unwrap();
+        let watermarks = Watermarks::new(&conf::WatermarkConfig::default()).unwrap();
+        let rec = Record {
+            key: Some(b"k".to_vec()),
+            value: Some(b"v".to_vec()),
+            headers: vec![
+                ("type".to_string(), b"order".to_vec()),
+                ("id".to_string(), b"1".to_vec()),
+            ],
+            topic: "orders".to_string(),
+            ..Default::default()
+        };
+        let mut flow_state = FlowState::new(
+            SinkRouter {
+                sinks: Sinks::default(),
+                partition: 0,
+            },
+            state_store.partition("swap", 0).unwrap(),
+            WatermarkView {
+                watermarks,
+                partition: 0,
+            },
+            TimerScheduler {
+                state: state_store.partition("swap-timers", 0).unwrap(),
+            },
+            false,
+        )
+        .unwrap();
+        flow_state.emitter = RecordCollector::new(&rec);
+        let mut store = Store::new(&engine, flow_state);
+        let instance = linker.instantiate_async(&mut store, &module).await.unwrap();
+        let processor = RecordProcessor::new(&mut store, &instance).unwrap();
+        let status = processor.process_record(&mut store, &rec).await.unwrap();
+        assert_eq!(status, Status::Ok);
+        let emitted = &store.data().emitter.records;
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].key.as_deref(), Some(&b"v"[..]));
+        assert_eq!(emitted[0].value.as_deref(), Some(&b"k"[..]));
+        assert_eq!(emitted[0].headers, rec.headers);
+        assert_eq!(emitted[0].topic, "orders");
+    }
+
+    #[tokio::test]
+    async fn test_required_exports() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let flow = |wat: &str| {
+            let path = dir.join(format!("{}.wat", uuid::Uuid::new_v4()));
+            std::fs::write(&path, wat).unwrap();
+            let cfg = FlowConfig::pipe(path, LineFormat::Json);
+            let input = Input::Stdin(StdinSource::new(&cfg.sources[0]).unwrap());
+            async move {
+                FlowProcessor::new(
+                    &cfg,
+                    opentelemetry::global::meter("wasmflow"),
+                    input,
+                    Sinks::new(&cfg.sinks, &cfg.routes).await.unwrap(),
+                    StateStore::new(None, None, None).unwrap(),
+                    Watermarks::new(&cfg.watermark).unwrap(),
+                )
+            }
+        };
+        assert!(flow(SWAP).await.is_ok());
+        let unallocating = SWAP.replace("\"canonical_abi_realloc\"", "\"realloc\"");
+        let err = flow(&unallocating).await.err().unwrap();
+        assert!(format!("{err:#}").contains("`canonical_abi_realloc`"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,20 @@
+pub mod admin;
+pub mod backpressure;
+pub mod cache;
+pub mod component;
 pub mod conf;
+pub mod dedup;
 pub mod flow;
+pub mod join;
+pub mod legacy;
+pub mod pipeline;
+pub mod ratelimit;
+pub mod record;
+pub mod reload;
+pub mod schema;
 pub mod sinks;
 pub mod sources;
+pub mod state;
+pub mod timer;
+pub mod watermark;
+pub mod window;
@@ -1,26 +1,156 @@
+use std::path::PathBuf;
+
 use anyhow::Context;
+use clap::{Parser, Subcommand};
 use opentelemetry_otlp::WithExportConfig;
+use tracing::error;
 use wasmflow::{
-    flow::FlowProcessor, sinks::s3::BufferedS3Sink, sources::kafka::create_kafka_consumer,
+    admin, cache,
+    conf::{self, FlowConfig, LineFormat},
+    flow::{self, FlowProcessor},
+    sinks::Sinks,
+    sources::{
+        file::FileSource,
+        http::HttpSource,
+        kafka::{self, check_co_partitioned, create_kafka_consumer, FlowConsumerContext},
+        stdin::StdinSource,
+        Input, PARTITION,
+    },
+    state::{changelog::Changelog, checkpoint::Checkpoints, StateStore},
+    watermark::Watermarks,
 };
 
+#[derive(Parser)]
+#[clap(version, about)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the flow described by the file in WASMFLOW_CONFIG (default).
+    Run {
+        /// Pipe the lines of stdin through this module to stdout instead,
+        /// without a config file, logs or metrics.
+        #[clap(long)]
+        module: Option<PathBuf>,
+        /// Read and write lines as JSON objects with key, value and headers
+        /// fields rather than as plain values.
+        #[clap(long, requires = "module")]
+        json: bool,
+    },
+    /// Move the consumer groups of the flow in WASMFLOW_CONFIG to the
+    /// `start_from` position of their sources, to reprocess records. The
+    /// flow must be stopped.
+    ResetOffsets,
+    /// Compile a WASM module ahead of time into a `.cwasm` artifact.
+    Compile {
+        module: PathBuf,
+        /// Output path, defaults to the module path with a `.cwasm` extension.
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let _pipeline = init_meter().with_context(|| "Could not initialize metrics exporter")?;
-
-    console_subscriber::init();
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Run {
+        module: None,
+        json: false,
+    });
+    match command {
+        Command::Run {
+            module: Some(module),
+            json,
+        } => {
+            let format = if json {
+                LineFormat::Json
+            } else {
+                LineFormat::Value
+            };
+            run(FlowConfig::pipe(module, format)).await
+        }
+        Command::Run { module: None, .. } => {
+            let _pipeline =
+                init_meter().with_context(|| "Could not initialize metrics exporter")?;
+            console_subscriber::init();
+            run(conf::read_config()?).await
+        }
+        Command::ResetOffsets => {
+            let cfg = conf::read_config()?;
+            let reset = kafka::reset_offsets(&cfg.sources)
+                .with_context(|| "Could not reset offsets, is the flow stopped?")?;
+            for elem in reset.elements() {
+                println!("{}/{}: {:?}", elem.topic(), elem.partition(), elem.offset());
+            }
+            Ok(())
+        }
+        Command::Compile { module, output } => {
+            let engine = flow::create_engine()?;
+            let output = cache::precompile(&engine, &module, output.as_deref())?;
+            println!("{}", output.display());
+            Ok(())
+        }
+    }
+}
 
-    let cfg = wasmflow::conf::read_config()?;
-    let kafka_consumer = create_kafka_consumer(&cfg.sources[0])?;
-    let s3_sink = BufferedS3Sink::new(&cfg.sinks[0]).await?;
+async fn run(cfg: FlowConfig) -> anyhow::Result<()> {
+    let changelog = cfg
+        .state
+        .as_ref()
+        .and_then(|s| s.changelog_topic.as_deref())
+        .map(|topic| Changelog::new(topic, &cfg.sources[0]))
+        .transpose()?;
+    let checkpoints = match cfg.state.as_ref().and_then(|s| s.checkpoint.as_ref()) {
+        Some(c) => Some(Checkpoints::new(c).await?),
+        None => None,
+    };
+    let state_store = StateStore::new(cfg.state.as_ref(), changelog, checkpoints)?;
+    if let Some(admin) = &cfg.admin {
+        let addr = admin.listen;
+        let state_store = state_store.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(addr, state_store).await {
+                error!(admin_error=?e);
+            }
+        });
+    }
+    let watermarks = Watermarks::new(&cfg.watermark)?;
+    let sinks = Sinks::new(&cfg.sinks, &cfg.routes).await?;
+    let local = match cfg.sources.as_slice() {
+        [source @ conf::Source::File { .. }] => Some(Input::File(FileSource::new(source)?)),
+        [source @ conf::Source::Stdin { .. }] => Some(Input::Stdin(StdinSource::new(source)?)),
+        [source @ conf::Source::Http { .. }] => {
+            Some(Input::Http(HttpSource::new(source, sinks.clone())?))
+        }
+        _ => None,
+    };
+    let input = if let Some(input) = local {
+        // The partition of sources other than Kafka is never revoked, so it
+        // is restored up front as if it had been assigned.
+        state_store.restore(&[PARTITION])?;
+        input
+    } else {
+        let kafka_consumer = create_kafka_consumer(
+            &cfg.sources,
+            FlowConsumerContext::new(state_store.clone(), watermarks.clone(), sinks.clone()),
+        )?;
+        if cfg.join.is_some() {
+            let topics: Vec<&str> = cfg
+                .sources
+                .iter()
+                .take(2)
+                .filter_map(|s| s.topic())
+                .collect();
+            check_co_partitioned(&kafka_consumer, &topics)?;
+        }
+        Input::Kafka(kafka_consumer)
+    };
     let meter = opentelemetry::global::meter("wasmflow");
-    let wasm_flow = FlowProcessor::new(
-        &cfg.processors[0].module_path,
-        meter,
-        kafka_consumer,
-        s3_sink,
-    )
-    .with_context(|| "Could not initialize WASM Flow")?;
+    let wasm_flow = FlowProcessor::new(&cfg, meter, input, sinks, state_store, watermarks)
+        .with_context(|| "Could not initialize WASM Flow")?;
     wasm_flow.run().await?;
     Ok(())
 }
@@ -0,0 +1,110 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::anyhow;
+
+use crate::cache::LoadedModule;
+use crate::component::{record_emitter, EmittedRecord};
+use crate::dedup::Dedup;
+use crate::join::Join;
+use crate::record::Record;
+use crate::window::Windowing;
+
+/// A processor in the pipeline. Records emitted by the module are passed to
+/// the next stage and to every branch; a module that emits nothing for a
+/// record filters it out.
+#[derive(Clone)]
+pub struct Stage {
+    pub name: String,
+    pub module: Arc<RwLock<LoadedModule>>,
+    pub branches: Vec<Vec<Stage>>,
+    pub window: Option<Arc<Windowing>>,
+    /// Drops duplicate records before they reach the module.
+    pub dedup: Option<Arc<Dedup>>,
+    /// Set on the stage joining the flow's sources, which runs first.
+    pub join: Option<Arc<Join>>,
+}
+
+/// The world a stage's component must export.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GuestWorld {
+    Processor,
+    Windowed,
+    Join,
+}
+
+impl Stage {
+    pub fn active_module(&self) -> anyhow::Result<LoadedModule> {
+        self.module
+            .read()
+            .map(|m| m.clone())
+            .map_err(|_| anyhow!("Active module lock poisoned"))
+    }
+
+    pub fn world(&self) -> GuestWorld {
+        GuestWorld::of(self.window.is_some(), self.join.is_some())
+    }
+
+    /// Visits this stage and all stages in its branches and late-record
+    /// pipeline.
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Stage)) {
+        f(self);
+        let late = self.window.iter().filter_map(|w| w.late.as_ref());
+        for branch in self.branches.iter().chain(late) {
+            for stage in branch {
+                stage.walk(f);
+            }
+        }
+    }
+}
+
+impl GuestWorld {
+    pub fn of(windowed: bool, join: bool) -> Self {
+        match (windowed, join) {
+            (_, true) => GuestWorld::Join,
+            (true, false) => GuestWorld::Windowed,
+            (false, false) => GuestWorld::Processor,
+        }
+    }
+}
+
+/// Collects the records a guest emits during a single invocation. Emitted
+/// records keep the topic, partition, offset and timestamp of their input.
+#[derive(Debug, Default)]
+pub struct RecordCollector {
+    pub input: Record,
+    pub records: Vec<Record>,
+}
+
+impl RecordCollector {
+    pub fn new(input: &Record) -> Self {
+        Self {
+            input: input.clone(),
+            records: Vec::new(),
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        key: Option<Vec<u8>>,
+        value: Option<Vec<u8>>,
+        headers: Vec<(String, Vec<u8>)>,
+    ) {
+        // The value is only known to be JSON if it is that of the input.
+        let json_value = self.input.json_value && value == self.input.value;
+        self.records.push(Record {
+            key,
+            value,
+            headers,
+            // The decoded value is that of the input, not of this record.
+            decoded: None,
+            json_value,
+            ..self.input.clone()
+        });
+    }
+}
+
+impl record_emitter::Host for RecordCollector {
+    async fn emit(&mut self, rec: EmittedRecord) {
+        self.push(rec.key, rec.value, rec.headers);
+    }
+}
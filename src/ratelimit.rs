@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+
+use crate::conf;
+
+/// Token bucket refilled continuously at the configured rate. Callers that
+/// find too few tokens wait for them in turn, so a limit slows its callers
+/// down rather than rejecting them.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    bucket: tokio::sync::Mutex<Bucket>,
+    throttled_us: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(cfg: &conf::RateLimit) -> anyhow::Result<Self> {
+        let burst = cfg.burst.unwrap_or(cfg.per_second);
+        if cfg.per_second == 0 || burst == 0 {
+            bail!("Rate limits must be positive");
+        }
+        Ok(Self {
+            rate: cfg.per_second as f64,
+            burst: burst as f64,
+            bucket: tokio::sync::Mutex::new(Bucket {
+                tokens: burst as f64,
+                refilled: Instant::now(),
+            }),
+            throttled_us: AtomicU64::new(0),
+        })
+    }
+
+    /// Takes `n` tokens, waiting until they are available. Requests larger
+    /// than the burst size take a full bucket.
+    pub async fn acquire(&self, n: u64) {
+        let n = (n as f64).min(self.burst);
+        let mut bucket = self.bucket.lock().await;
+        self.refill(&mut bucket);
+        if bucket.tokens < n {
+            let wait = Duration::from_secs_f64((n - bucket.tokens) / self.rate);
+            self.throttled_us
+                .fetch_add(wait.as_micros() as u64, Ordering::Relaxed);
+            tokio::time::sleep(wait).await;
+            self.refill(&mut bucket);
+        }
+        bucket.tokens -= n;
+    }
+
+    /// Tokens currently available, or `None` while a caller is waiting.
+    pub fn available(&self) -> Option<f64> {
+        let mut bucket = self.bucket.try_lock().ok()?;
+        self.refill(&mut bucket);
+        Some(bucket.tokens)
+    }
+
+    /// Total time callers were made to wait.
+    pub fn throttled(&self) -> Duration {
+        Duration::from_micros(self.throttled_us.load(Ordering::Relaxed))
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let bucket = TokenBucket::new(&conf::RateLimit {
+            per_second: 100,
+            burst: Some(2),
+        })
+        .unwrap();
+        let start = Instant::now();
+        bucket.acquire(1).await;
+        bucket.acquire(1).await;
+        assert_eq!(bucket.throttled(), Duration::ZERO);
+        // Larger than the burst, so it waits for a full bucket.
+        bucket.acquire(5).await;
+        assert!(bucket.throttled() >= Duration::from_millis(15));
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert!(bucket.available().unwrap() < 1.0);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Context;
+use rdkafka::{
+    message::{BorrowedMessage, Headers},
+    Message,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::conf::LineFormat;
+use crate::schema::Decoded;
+use crate::state::codec::{put_bytes, take, take_bytes, take_u64};
+
+/// A record as a line of JSON.
+#[derive(Default, Serialize, Deserialize)]
+struct JsonRecord {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+}
+
+/// An owned record flowing through the processor pipeline.
+#[derive(Clone, Debug, Default)]
+pub struct Record {
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub timestamp: i64,
+    /// Value decoded from the Schema Registry wire format, for sources that
+    /// decode to structured values. It is not kept in the state store.
+    pub decoded: Option<Arc<Decoded>>,
+    /// Whether the value was read from JSON other than a string, so that it
+    /// is written back as JSON in JSON lines. It is not kept in the state
+    /// store.
+    pub json_value: bool,
+}
+
+impl Record {
+    pub fn from_message(msg: &BorrowedMessage<'_>) -> Self {
+        let mut headers = Vec::new();
+        if let Some(hdrs) = msg.headers() {
+            for h in hdrs.iter() {
+                headers.push((h.key.to_string(), h.value.unwrap_or_default().to_vec()));
+            }
+        }
+        Self {
+            key: msg.key().map(<[u8]>::to_vec),
+            value: msg.payload().map(<[u8]>::to_vec),
+            headers,
+            topic: msg.topic().to_string(),
+            partition: msg.partition(),
+            offset: msg.offset(),
+            timestamp: msg.timestamp().to_millis().unwrap_or(-1),
+            decoded: None,
+            json_value: false,
+        }
+    }
+
+    /// Parses a line of text without its newline into the key, value and
+    /// headers of a record.
+    pub fn from_line(line: Vec<u8>, format: LineFormat) -> anyhow::Result<Self> {
+        if format == LineFormat::Value {
+            return Ok(Self {
+                value: Some(line),
+                ..Default::default()
+            });
+        }
+        let json = serde_json::from_slice(&line).with_context(|| "Line is not JSON")?;
+        Self::from_json(json)
+    }
+
+    /// Reads the key, value and headers of a record from a JSON object in
+    /// the format of `LineFormat::Json`.
+    pub fn from_json(json: serde_json::Value) -> anyhow::Result<Self> {
+        let json: JsonRecord =
+            serde_json::from_value(json).with_context(|| "Invalid JSON record")?;
+        let (value, json_value) = match json.value {
+            None | Some(serde_json::Value::Null) => (None, false),
+            Some(serde_json::Value::String(s)) => (Some(s.into_bytes()), false),
+            Some(v) => (Some(serde_json::to_vec(&v)?), true),
+        };
+        Ok(Self {
+            key: json.key.map(String::into_bytes),
+            value,
+            json_value,
+            headers: json
+                .headers
+                .into_iter()
+                .map(|(k, v)| (k, v.into_bytes()))
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// The record as a line of text without its newline. Keys, values and
+    /// headers are written as UTF-8 in JSON lines, replacing invalid bytes.
+    /// Values read from JSON other than a string are written back as that
+    /// JSON, so that lines read back the same.
+    pub fn to_line(&self, format: LineFormat) -> anyhow::Result<Vec<u8>> {
+        if format == LineFormat::Value {
+            return Ok(self.value.clone().unwrap_or_default());
+        }
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        let json = JsonRecord {
+            key: self.key.as_deref().map(text),
+            value: self.value.as_deref().map(|v| {
+                self.json_value
+                    .then(|| serde_json::from_slice(v).ok())
+                    .flatten()
+                    .unwrap_or_else(|| serde_json::Value::String(text(v)))
+            }),
+            headers: self
+                .headers
+                .iter()
+                .map(|(k, v)| (k.clone(), text(v)))
+                .collect(),
+        };
+        Ok(serde_json::to_vec(&json)?)
+    }
+
+    /// Headers borrowed in the shape expected by the guest bindings.
+    pub fn header_refs(&self) -> Vec<(&str, &[u8])> {
+        self.headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_slice()))
+            .collect()
+    }
+
+    /// Encoding used to keep records in the state store.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for field in [&self.key, &self.value] {
+            match field {
+                Some(bytes) => {
+                    buf.push(1);
+                    put_bytes(&mut buf, bytes);
+                }
+                None => buf.push(0),
+            }
+        }
+        buf.extend_from_slice(&(self.headers.len() as u64).to_be_bytes());
+        for (k, v) in &self.headers {
+            put_bytes(&mut buf, k.as_bytes());
+            put_bytes(&mut buf, v);
+        }
+        put_bytes(&mut buf, self.topic.as_bytes());
+        buf.extend_from_slice(&self.partition.to_be_bytes());
+        buf.extend_from_slice(&self.offset.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> anyhow::Result<Self> {
+        let optional = |buf: &mut &[u8]| -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(match take(buf, 1)?[0] {
+                0 => None,
+                _ => Some(take_bytes(buf)?.to_vec()),
+            })
+        };
+        let key = optional(&mut buf)?;
+        let value = optional(&mut buf)?;
+        let mut headers = Vec::new();
+        for _ in 0..take_u64(&mut buf)? {
+            let k = String::from_utf8(take_bytes(&mut buf)?.to_vec())?;
+            headers.push((k, take_bytes(&mut buf)?.to_vec()));
+        }
+        Ok(Self {
+            key,
+            value,
+            headers,
+            topic: String::from_utf8(take_bytes(&mut buf)?.to_vec())?,
+            partition: i32::from_be_bytes(take(&mut buf, 4)?.try_into()?),
+            offset: i64::from_be_bytes(take(&mut buf, 8)?.try_into()?),
+            timestamp: i64::from_be_bytes(take(&mut buf, 8)?.try_into()?),
+            decoded: None,
+            json_value: false,
+        })
+    }
+}
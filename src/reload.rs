@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use opentelemetry::{metrics::Counter, KeyValue};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+use crate::cache::{self, ModuleCache};
+use crate::conf;
+use crate::flow::FlowContext;
+use crate::pipeline::Stage;
+
+/// Swaps the processor module when `module_path` changes on disk or when the
+/// process receives SIGHUP.
+#[derive(Clone, Debug)]
+pub struct ModuleReloader {
+    module_path: PathBuf,
+    cache: Option<ModuleCache>,
+    interval: Option<Duration>,
+}
+
+impl ModuleReloader {
+    pub fn new(processor: &conf::Processor, cache: Option<ModuleCache>) -> Self {
+        Self {
+            module_path: processor.module_path.clone(),
+            cache,
+            interval: processor.reload_interval_secs.map(Duration::from_secs),
+        }
+    }
+
+    pub async fn watch(self, fctx: FlowContext, stage: Stage, reloads: Counter<u64>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!(module_reload_signal_error=%e);
+                return;
+            }
+        };
+        let mut last_modified = self.modified();
+        loop {
+            match self.interval {
+                Some(interval) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep(interval) => {
+                            let modified = self.modified();
+                            if modified == last_modified {
+                                continue;
+                            }
+                            last_modified = modified;
+                        }
+                        _ = hangup.recv() => {}
+                    }
+                }
+                None => {
+                    hangup.recv().await;
+                }
+            }
+            // Compilation can take seconds, keep it off the async workers.
+            let reloader = self.clone();
+            let ctx = fctx.clone();
+            let target = stage.clone();
+            let result = tokio::task::spawn_blocking(move || reloader.reload(&ctx, &target))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|r| r);
+            let status = match result {
+                Ok(true) => "ok",
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!(module_reload_rejected=?e);
+                    "rejected"
+                }
+            };
+            let kv = [
+                KeyValue::new("processor", stage.name.clone()),
+                KeyValue::new("status", status),
+            ];
+            reloads.add(1, &kv);
+        }
+    }
+
+    /// Loads the module from disk and swaps it in. Returns `false` if the
+    /// module on disk is the one already active.
+    fn reload(&self, fctx: &FlowContext, stage: &Stage) -> anyhow::Result<bool> {
+        let active_hash = stage.active_module()?.hash;
+        let candidate = cache::load_module(&fctx.engine, &self.module_path, self.cache.as_ref())?;
+        if candidate.hash == active_hash {
+            return Ok(false);
+        }
+        let hash = candidate.hash.clone();
+        fctx.swap_module(stage, candidate)?;
+        info!(processor=%stage.name, module_reloaded=?self.module_path, module_hash=%hash);
+        Ok(true)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.module_path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{DedupConfig, DedupId, FlowConfig, LineFormat};
+    use crate::dedup::Dedup;
+    use crate::flow::FlowProcessor;
+    use crate::sinks::Sinks;
+    use crate::sources::stdin::StdinSource;
+    use crate::sources::Input;
+    use crate::state::StateStore;
+    use crate::watermark::Watermarks;
+    use std::sync::Arc;
+
+    /// Has the exports of a legacy processor, which are all that is checked
+    /// before a swap.
+    const PROCESSOR: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "canonical_abi_realloc") (param i32 i32 i32 i32) (result i32)
+            (i32.const 0))
+        (func (export "process-record")))"#;
+
+    #[tokio::test]
+    async fn test_swap_module() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("processor.wat");
+        std::fs::write(&path, PROCESSOR).unwrap();
+        let cfg = FlowConfig::pipe(path.clone(), LineFormat::Json);
+        let input = Input::Stdin(StdinSource::new(&cfg.sources[0]).unwrap());
+        let flow = FlowProcessor::new(
+            &cfg,
+            opentelemetry::global::meter("wasmflow"),
+            input,
+            Sinks::new(&cfg.sinks, &cfg.routes).await.unwrap(),
+            StateStore::new(None, None, None).unwrap(),
+            Watermarks::new(&cfg.watermark).unwrap(),
+        )
+        .unwrap();
+        let fctx = &flow.flow_context;
+        let stage = &fctx.pipeline[0];
+        let reloader = ModuleReloader::new(&cfg.processors[0], None);
+        let active = stage.active_module().unwrap().hash;
+        assert!(!reloader.reload(fctx, stage).unwrap());
+
+        // A core module without `process-record`.
+        let missing = PROCESSOR.replace("\"process-record\"", "\"process\"");
+        std::fs::write(&path, missing).unwrap();
+        let err = reloader.reload(fctx, stage).unwrap_err();
+        assert!(format!("{err:#}").contains("`process-record`"), "{err:#}");
+        assert_eq!(stage.active_module().unwrap().hash, active);
+
+        // A component that does not export the processor world.
+        std::fs::write(&path, [0x00, 0x61, 0x73, 0x6d, 0x0d, 0x00, 0x01, 0x00]).unwrap();
+        let err = reloader.reload(fctx, stage).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("wasmflow:processor world"),
+            "{err:#}"
+        );
+        assert_eq!(stage.active_module().unwrap().hash, active);
+
+        // A core module for a stage whose dedup ids are computed by the guest.
+        let other = PROCESSOR.replace("(memory", "(func (export \"other\")) (memory");
+        std::fs::write(&path, other).unwrap();
+        let dedup = DedupConfig {
+            id: DedupId::Guest,
+            horizon_ms: 1000,
+        };
+        let deduplicating = Stage {
+            dedup: Some(Arc::new(Dedup::new(&dedup).unwrap())),
+            ..stage.clone()
+        };
+        let err = reloader.reload(fctx, &deduplicating).unwrap_err();
+        assert!(format!("{err:#}").contains("need a component"), "{err:#}");
+        assert_eq!(stage.active_module().unwrap().hash, active);
+
+        // The same module is swapped in for a stage without dedup.
+        assert!(reloader.reload(fctx, stage).unwrap());
+        assert_ne!(stage.active_module().unwrap().hash, active);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
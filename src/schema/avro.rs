@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Map, Value};
+
+use super::bytes_to_json;
+use crate::state::codec::take;
+
+/// An Avro schema parsed from its JSON form. Named types are kept in a table
+/// and referred to by full name, so that schemas may be recursive and may use
+/// types defined by referenced schemas.
+#[derive(Debug, Default)]
+pub struct Schema {
+    root: Type,
+    names: HashMap<String, Type>,
+}
+
+#[derive(Debug, Default)]
+enum Type {
+    #[default]
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Type)>),
+    Enum(Vec<String>),
+    Array(Box<Type>),
+    Map(Box<Type>),
+    Union(Vec<Type>),
+    Fixed(usize),
+    Named(String),
+}
+
+impl Schema {
+    /// Parses `schema` after the schemas it references, which define named
+    /// types it may use.
+    pub fn parse(schema: &str, references: &[String]) -> anyhow::Result<Self> {
+        let mut names = HashMap::new();
+        for reference in references {
+            let json: Value = serde_json::from_str(reference)
+                .with_context(|| "Referenced Avro schema is not JSON")?;
+            parse_type(&json, None, &mut names)?;
+        }
+        let json: Value =
+            serde_json::from_str(schema).with_context(|| "Avro schema is not JSON")?;
+        let root = parse_type(&json, None, &mut names)?;
+        Ok(Self { root, names })
+    }
+
+    /// Decodes a binary encoded datum into JSON. Unions are unwrapped to the
+    /// value of their branch, bytes and fixed become base64 strings and
+    /// logical types are left as their underlying type.
+    pub fn decode(&self, mut buf: &[u8]) -> anyhow::Result<Value> {
+        let value = self.decode_type(&self.root, &mut buf)?;
+        if !buf.is_empty() {
+            bail!("{} trailing bytes after Avro datum", buf.len());
+        }
+        Ok(value)
+    }
+
+    fn decode_type(&self, t: &Type, buf: &mut &[u8]) -> anyhow::Result<Value> {
+        Ok(match t {
+            Type::Null => Value::Null,
+            Type::Boolean => Value::Bool(take(buf, 1)?[0] != 0),
+            Type::Int | Type::Long => Value::from(read_long(buf)?),
+            Type::Float => Value::from(f32::from_le_bytes(take(buf, 4)?.try_into()?)),
+            Type::Double => Value::from(f64::from_le_bytes(take(buf, 8)?.try_into()?)),
+            Type::Bytes => bytes_to_json(read_bytes(buf)?),
+            Type::String => Value::String(std::str::from_utf8(read_bytes(buf)?)?.to_string()),
+            Type::Record(fields) => {
+                let mut object = Map::new();
+                for (name, field) in fields {
+                    object.insert(name.clone(), self.decode_type(field, buf)?);
+                }
+                Value::Object(object)
+            }
+            Type::Enum(symbols) => {
+                let index = read_long(buf)?;
+                let symbol = usize::try_from(index)
+                    .ok()
+                    .and_then(|i| symbols.get(i))
+                    .ok_or_else(|| anyhow!("Enum index {index} out of range"))?;
+                Value::String(symbol.clone())
+            }
+            Type::Array(items) => {
+                let mut array = Vec::new();
+                while let Some(count) = read_block(buf)? {
+                    for _ in 0..count {
+                        array.push(self.decode_type(items, buf)?);
+                    }
+                }
+                Value::Array(array)
+            }
+            Type::Map(values) => {
+                let mut object = Map::new();
+                while let Some(count) = read_block(buf)? {
+                    for _ in 0..count {
+                        let key = std::str::from_utf8(read_bytes(buf)?)?.to_string();
+                        object.insert(key, self.decode_type(values, buf)?);
+                    }
+                }
+                Value::Object(object)
+            }
+            Type::Union(branches) => {
+                let index = read_long(buf)?;
+                let branch = usize::try_from(index)
+                    .ok()
+                    .and_then(|i| branches.get(i))
+                    .ok_or_else(|| anyhow!("Union index {index} out of range"))?;
+                self.decode_type(branch, buf)?
+            }
+            Type::Fixed(size) => bytes_to_json(take(buf, *size)?),
+            Type::Named(name) => self.decode_type(self.resolve(name)?, buf)?,
+        })
+    }
+
+    /// Looks up a named type, falling back to the name without namespace
+    /// for types referred to from another namespace.
+    fn resolve(&self, name: &str) -> anyhow::Result<&Type> {
+        let short = name.rsplit('.').next().unwrap_or(name);
+        self.names
+            .get(name)
+            .or_else(|| self.names.get(short))
+            .ok_or_else(|| anyhow!("Unknown Avro type {name}"))
+    }
+}
+
+fn parse_type(
+    json: &Value,
+    namespace: Option<&str>,
+    names: &mut HashMap<String, Type>,
+) -> anyhow::Result<Type> {
+    let object = match json {
+        Value::String(name) => {
+            return Ok(primitive(name).unwrap_or_else(|| Type::Named(full_name(name, namespace))))
+        }
+        Value::Array(branches) => {
+            return Ok(Type::Union(
+                branches
+                    .iter()
+                    .map(|b| parse_type(b, namespace, names))
+                    .collect::<anyhow::Result<_>>()?,
+            ))
+        }
+        Value::Object(object) => object,
+        _ => bail!("Invalid Avro schema: {json}"),
+    };
+    let kind = object
+        .get("type")
+        .ok_or_else(|| anyhow!("Avro schema without type: {json}"))?;
+    let kind = match kind {
+        Value::String(kind) => kind.as_str(),
+        // A type wrapped in an object, possibly with a logical type.
+        _ => return parse_type(kind, namespace, names),
+    };
+    let name = || -> anyhow::Result<String> {
+        let name = object
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Named Avro type without name: {json}"))?;
+        let namespace = object
+            .get("namespace")
+            .and_then(Value::as_str)
+            .or(namespace);
+        Ok(full_name(name, namespace))
+    };
+    let t = match kind {
+        "record" | "error" => {
+            let name = name()?;
+            let namespace = name.rsplit_once('.').map(|(ns, _)| ns.to_string());
+            let mut fields = Vec::new();
+            for field in object
+                .get("fields")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("Avro record {name} without fields"))?
+            {
+                let field_name = field
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| anyhow!("Field of Avro record {name} without name"))?;
+                let field_type = field
+                    .get("type")
+                    .ok_or_else(|| anyhow!("Field {field_name} of {name} without type"))?;
+                fields.push((
+                    field_name.to_string(),
+                    parse_type(field_type, namespace.as_deref(), names)?,
+                ));
+            }
+            names.insert(name.clone(), Type::Record(fields));
+            Type::Named(name)
+        }
+        "enum" => {
+            let name = name()?;
+            let symbols = object
+                .get("symbols")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("Avro enum {name} without symbols"))?
+                .iter()
+                .map(|s| s.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| anyhow!("Avro enum {name} has invalid symbols"))?;
+            names.insert(name.clone(), Type::Enum(symbols));
+            Type::Named(name)
+        }
+        "fixed" => {
+            let name = name()?;
+            let size = object
+                .get("size")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| anyhow!("Avro fixed {name} without size"))?;
+            names.insert(name.clone(), Type::Fixed(usize::try_from(size)?));
+            Type::Named(name)
+        }
+        "array" => Type::Array(Box::new(parse_type(
+            object
+                .get("items")
+                .ok_or_else(|| anyhow!("Avro array without items"))?,
+            namespace,
+            names,
+        )?)),
+        "map" => Type::Map(Box::new(parse_type(
+            object
+                .get("values")
+                .ok_or_else(|| anyhow!("Avro map without values"))?,
+            namespace,
+            names,
+        )?)),
+        other => primitive(other).ok_or_else(|| anyhow!("Unknown Avro type {other}"))?,
+    };
+    Ok(t)
+}
+
+fn primitive(name: &str) -> Option<Type> {
+    Some(match name {
+        "null" => Type::Null,
+        "boolean" => Type::Boolean,
+        "int" => Type::Int,
+        "long" => Type::Long,
+        "float" => Type::Float,
+        "double" => Type::Double,
+        "bytes" => Type::Bytes,
+        "string" => Type::String,
+        _ => return None,
+    })
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(ns) if !name.contains('.') && !ns.is_empty() => format!("{ns}.{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// Reads a zig-zag encoded variable length integer.
+fn read_long(buf: &mut &[u8]) -> anyhow::Result<i64> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(buf, 1)?[0];
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((n >> 1) as i64 ^ -((n & 1) as i64));
+        }
+    }
+    bail!("Avro integer is too long")
+}
+
+fn read_bytes<'a>(buf: &mut &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    let len = usize::try_from(read_long(buf)?).with_context(|| "Negative Avro length")?;
+    take(buf, len)
+}
+
+/// Reads the item count of the next block of an array or map, `None` at the
+/// end. Blocks with a negative count are followed by their size in bytes.
+///
+/// Items take at least a byte except for those like `null`, of which a
+/// corrupt count could otherwise make for an endless array, so counts are
+/// capped by the bytes left.
+fn read_block(buf: &mut &[u8]) -> anyhow::Result<Option<u64>> {
+    let count = read_long(buf)?;
+    if count < 0 {
+        read_long(buf)?;
+    }
+    let remaining = buf.len() as u64;
+    if count.unsigned_abs() > remaining {
+        bail!("Avro block of {count} items exceeds the {remaining} bytes left");
+    }
+    Ok((count != 0).then_some(count.unsigned_abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_decode() {
+        let reference = r#"{"type": "enum", "name": "Kind", "namespace": "shop",
+            "symbols": ["ONLINE", "STORE"]}"#;
+        let schema = r#"{"type": "record", "name": "Order", "namespace": "shop", "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "kind", "type": "Kind"},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "note", "type": ["null", "string"]},
+            {"name": "next", "type": ["null", "Order"]}
+        ]}"#;
+        let schema = Schema::parse(schema, &[reference.to_string()]).unwrap();
+        let datum = [
+            0x54, // id 42
+            0x02, // STORE
+            0x02, 0x02, b'a', 0x00, // ["a"]
+            0x00, // note null
+            0x02, // next is an Order
+            0x01, 0x00, 0x00, 0x02, 0x02, b'x', 0x00, // id -1, ONLINE, [], "x", null
+        ];
+        assert_eq!(
+            schema.decode(&datum).unwrap(),
+            serde_json::json!({
+                "id": 42, "kind": "STORE", "tags": ["a"], "note": null,
+                "next": {"id": -1, "kind": "ONLINE", "tags": [], "note": "x", "next": null}
+            })
+        );
+        assert!(schema.decode(&datum[..5]).is_err());
+
+        let nulls = Schema::parse(r#"{"type": "array", "items": "null"}"#, &[]).unwrap();
+        assert_eq!(
+            nulls.decode(&[0x02, 0x00]).unwrap(),
+            serde_json::json!([null])
+        );
+        let huge = [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x00];
+        assert!(nulls.decode(&huge).is_err());
+        let map = Schema::parse(r#"{"type": "map", "values": "null"}"#, &[]).unwrap();
+        assert!(map.decode(&huge).is_err());
+    }
+}
@@ -0,0 +1,449 @@
+//! Decoding of record values written by Confluent Schema Registry
+//! serializers: a zero magic byte and a big-endian schema id, followed by
+//! the Avro, Protobuf or JSON payload.
+
+pub mod avro;
+pub mod protobuf;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use base64::Engine;
+use futures::{future::BoxFuture, FutureExt};
+use hyper::client::HttpConnector;
+use hyper::header::{ACCEPT, AUTHORIZATION};
+use hyper::{Body, Client, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::Value;
+use tracing::error;
+
+use crate::component::{decoded, Status};
+use crate::conf;
+use crate::record::Record;
+use crate::sinks::Sinks;
+
+/// Header added to decoded records with the id of their schema.
+pub const SCHEMA_ID_HEADER: &str = "schema-id";
+/// Header added to records sent to the dead-letter sink with the reason.
+pub const ERROR_HEADER: &str = "wasmflow-error";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Decoders of the sources of a flow, by the topics they read.
+#[derive(Debug, Default)]
+pub struct Decoders {
+    sources: Vec<(Vec<String>, Decoder)>,
+    single: bool,
+}
+
+impl Decoders {
+    pub fn new(sources: &[conf::Source], sinks: &Sinks) -> anyhow::Result<Self> {
+        let mut decoders = Vec::new();
+        for source in sources {
+            let cfg = match source {
+                conf::Source::Kafka {
+                    schema_registry: Some(cfg),
+                    ..
+                } => cfg,
+                _ => continue,
+            };
+            if let Some(sink) = cfg.dead_letter.as_deref().filter(|s| !sinks.contains(s)) {
+                bail!("Unknown dead_letter sink {sink}");
+            }
+            let topics = source.topics().into_iter().map(str::to_string).collect();
+            decoders.push((topics, Decoder::new(cfg)));
+        }
+        Ok(Self {
+            sources: decoders,
+            single: sources.len() == 1,
+        })
+    }
+
+    /// Decoder of the source reading `topic`. With a single source, this
+    /// includes the topics matched by its patterns.
+    pub fn for_topic(&self, topic: &str) -> Option<&Decoder> {
+        self.sources
+            .iter()
+            .find(|(topics, _)| self.single || topics.iter().any(|t| t == topic))
+            .map(|(_, decoder)| decoder)
+    }
+
+    /// Records that could not be decoded so far.
+    pub fn failed(&self) -> u64 {
+        self.sources.iter().map(|(_, d)| d.failed()).sum()
+    }
+}
+
+#[derive(Debug)]
+pub struct Decoder {
+    registry: SchemaRegistry,
+    output: conf::DecodeOutput,
+    dead_letter: Option<String>,
+    failed: AtomicU64,
+}
+
+impl Decoder {
+    pub fn new(cfg: &conf::SchemaRegistryConfig) -> Self {
+        Self {
+            registry: SchemaRegistry::new(cfg),
+            output: cfg.output,
+            dead_letter: cfg.dead_letter.clone(),
+            failed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Decodes the value of `rec`, adding the `schema-id` header. As JSON,
+    /// the value is replaced by its canonical JSON. As a structured value, it
+    /// is kept as is and the decoded value is attached for guests to read
+    /// through the `decoded` interface. Records without a value are kept.
+    pub async fn decode(&self, rec: &mut Record) -> anyhow::Result<()> {
+        let value = match &rec.value {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        if value.len() < 5 || value[0] != 0 {
+            bail!("Value is not in the Schema Registry wire format");
+        }
+        let (schema_id, payload) = (u32::from_be_bytes(value[1..5].try_into()?), &value[5..]);
+        let schema = self.registry.schema(schema_id).await?;
+        let decoded = schema
+            .decode(payload)
+            .with_context(|| format!("Could not decode value with schema {schema_id}"))?;
+        rec.headers.push((
+            SCHEMA_ID_HEADER.to_string(),
+            schema_id.to_string().into_bytes(),
+        ));
+        match self.output {
+            conf::DecodeOutput::Json => rec.value = Some(serde_json::to_vec(&decoded)?),
+            conf::DecodeOutput::Structured => {
+                rec.decoded = Some(Arc::new(Decoded {
+                    schema_id,
+                    value: decoded,
+                }))
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `rec`, which could not be decoded, to the dead-letter sink with
+    /// the reason in the `wasmflow-error` header. Without a dead-letter sink,
+    /// the record is dropped.
+    pub async fn dead_letter(&self, sinks: &Sinks, mut rec: Record, err: &anyhow::Error) -> Status {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        error!(
+            topic = %rec.topic,
+            partition = rec.partition,
+            offset = rec.offset,
+            decode_error = ?err
+        );
+        match &self.dead_letter {
+            Some(sink) => {
+                rec.headers
+                    .push((ERROR_HEADER.to_string(), format!("{err:#}").into_bytes()));
+                sinks.send(sink, &rec).await
+            }
+            None => Status::Error,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Schema {
+    Avro(avro::Schema),
+    Protobuf(protobuf::Schema),
+    /// Payloads are parsed as JSON without validating them.
+    Json,
+}
+
+impl Schema {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Value> {
+        match self {
+            Schema::Avro(schema) => schema.decode(payload),
+            Schema::Protobuf(schema) => schema.decode(payload),
+            Schema::Json => serde_json::from_slice(payload).with_context(|| "Invalid JSON payload"),
+        }
+    }
+}
+
+/// Client of a Schema Registry that keeps the schemas it fetched.
+pub struct SchemaRegistry {
+    url: String,
+    authorization: Option<String>,
+    client: Client<HttpsConnector<HttpConnector>>,
+    schemas: Mutex<HashMap<u32, Arc<Schema>>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SchemaResponse {
+    schema: String,
+    #[serde(default)]
+    schema_type: Option<String>,
+    #[serde(default)]
+    references: Vec<Reference>,
+}
+
+#[derive(Deserialize)]
+struct Reference {
+    subject: String,
+    version: i32,
+}
+
+impl std::fmt::Debug for SchemaRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SchemaRegistry")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+impl SchemaRegistry {
+    pub fn new(cfg: &conf::SchemaRegistryConfig) -> Self {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let authorization = cfg.username.as_ref().map(|username| {
+            let password = cfg.password.as_deref().unwrap_or_default();
+            let credentials = format!("{username}:{password}");
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        });
+        Self {
+            url: cfg.url.trim_end_matches('/').to_string(),
+            authorization,
+            client: Client::builder().build(connector),
+            schemas: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Schema with `id`, fetched on first use. Failed fetches are not kept,
+    /// so they are retried with the next record using the schema.
+    async fn schema(&self, id: u32) -> anyhow::Result<Arc<Schema>> {
+        if let Some(schema) = self.cache()?.get(&id) {
+            return Ok(schema.clone());
+        }
+        let fetched: SchemaResponse = self.get(&format!("/schemas/ids/{id}")).await?;
+        let references = self.references(&fetched.references).await?;
+        let schema = match fetched.schema_type.as_deref() {
+            None | Some("AVRO") => Schema::Avro(avro::Schema::parse(&fetched.schema, &references)?),
+            Some("PROTOBUF") => {
+                Schema::Protobuf(protobuf::Schema::parse(&fetched.schema, &references)?)
+            }
+            Some("JSON") => Schema::Json,
+            Some(other) => bail!("Unsupported schema type {other}"),
+        };
+        let schema = Arc::new(schema);
+        self.cache()?.insert(id, schema.clone());
+        Ok(schema)
+    }
+
+    /// Sources of the schemas referenced by a schema, each preceded by the
+    /// schemas it references in turn.
+    fn references<'a>(
+        &'a self,
+        references: &'a [Reference],
+    ) -> BoxFuture<'a, anyhow::Result<Vec<String>>> {
+        async move {
+            let mut sources = Vec::new();
+            for r in references {
+                let subject = encode_segment(&r.subject);
+                let path = format!("/subjects/{subject}/versions/{}", r.version);
+                let referenced: SchemaResponse = self.get(&path).await?;
+                sources.extend(self.references(&referenced.references).await?);
+                sources.push(referenced.schema);
+            }
+            Ok(sources)
+        }
+        .boxed()
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> anyhow::Result<T> {
+        let mut req = Request::get(format!("{}{path}", self.url))
+            .header(ACCEPT, "application/vnd.schemaregistry.v1+json");
+        if let Some(authorization) = &self.authorization {
+            req = req.header(AUTHORIZATION, authorization);
+        }
+        let resp = tokio::time::timeout(TIMEOUT, self.client.request(req.body(Body::empty())?))
+            .await
+            .with_context(|| format!("Schema Registry request timed out: {path}"))?
+            .with_context(|| format!("Schema Registry request failed: {path}"))?;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        if !status.is_success() {
+            bail!(
+                "Schema Registry returned {status} for {path}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+        serde_json::from_slice(&body)
+            .with_context(|| format!("Invalid Schema Registry response for {path}"))
+    }
+
+    fn cache(&self) -> anyhow::Result<std::sync::MutexGuard<'_, HashMap<u32, Arc<Schema>>>> {
+        self.schemas
+            .lock()
+            .map_err(|_| anyhow!("Schema cache lock poisoned"))
+    }
+}
+
+/// A value decoded by the host, which guests read through the `decoded`
+/// interface.
+#[derive(Debug)]
+pub struct Decoded {
+    pub schema_id: u32,
+    pub value: Value,
+}
+
+/// The decoded value of the record being processed by a guest.
+#[derive(Clone, Debug, Default)]
+pub struct DecodedView {
+    pub decoded: Option<Arc<Decoded>>,
+}
+
+impl decoded::Host for DecodedView {
+    async fn value(&mut self) -> Option<Vec<decoded::ValueNode>> {
+        self.decoded.as_ref().map(|d| {
+            let mut nodes = Vec::new();
+            push_node(&d.value, &mut nodes);
+            nodes
+        })
+    }
+
+    async fn schema_id(&mut self) -> Option<u32> {
+        self.decoded.as_ref().map(|d| d.schema_id)
+    }
+}
+
+/// Appends `value` and its children to `nodes` with the value first,
+/// returning its index.
+fn push_node(value: &Value, nodes: &mut Vec<decoded::ValueNode>) -> u32 {
+    use decoded::ValueNode;
+    let index = nodes.len();
+    nodes.push(ValueNode::Null);
+    let node = match value {
+        Value::Null => ValueNode::Null,
+        Value::Bool(b) => ValueNode::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => ValueNode::Integer(i),
+            None => ValueNode::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => ValueNode::Text(s.clone()),
+        Value::Array(items) => {
+            ValueNode::Array(items.iter().map(|item| push_node(item, nodes)).collect())
+        }
+        Value::Object(fields) => ValueNode::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), push_node(v, nodes)))
+                .collect(),
+        ),
+    };
+    nodes[index] = node;
+    index as u32
+}
+
+/// Bytes in JSON, as base64 like the proto3 JSON mapping does.
+fn bytes_to_json(bytes: &[u8]) -> Value {
+    Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// Percent-encodes all but the unreserved characters of RFC 3986, so that
+/// `s` is a single path segment.
+fn encode_segment(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use std::convert::Infallible;
+
+    /// Serves an Avro schema as id 7, which references a subject, and counts
+    /// the requests it gets.
+    async fn stub_registry(requests: Arc<AtomicU64>) -> String {
+        let make_svc = make_service_fn(move |_conn| {
+            let requests = requests.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    requests.fetch_add(1, Ordering::Relaxed);
+                    let body = match req.uri().path() {
+                        "/schemas/ids/7" => serde_json::json!({
+                            "schema": r#"{"type": "record", "name": "T",
+                                "fields": [{"name": "kind", "type": "Kind"}]}"#,
+                            "references": [{"name": "Kind", "subject": "kinds/a b", "version": 1}]
+                        }),
+                        "/subjects/kinds%2Fa%20b/versions/1" => serde_json::json!({
+                            "schema": r#"{"type": "enum", "name": "Kind", "symbols": ["A", "B"]}"#
+                        }),
+                        _ => serde_json::json!({"error_code": 40403}),
+                    };
+                    let mut resp = Response::new(Body::from(body.to_string()));
+                    if body.get("error_code").is_some() {
+                        *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+                    }
+                    async move { Ok::<_, Infallible>(resp) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        url
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let requests = Arc::new(AtomicU64::new(0));
+        let cfg = conf::SchemaRegistryConfig {
+            url: stub_registry(requests.clone()).await,
+            username: None,
+            password: None,
+            output: conf::DecodeOutput::Json,
+            dead_letter: None,
+        };
+        let decoder = Decoder::new(&cfg);
+        for _ in 0..2 {
+            let mut rec = Record {
+                value: Some(vec![0, 0, 0, 0, 7, 2]),
+                ..Default::default()
+            };
+            decoder.decode(&mut rec).await.unwrap();
+            assert_eq!(rec.value.as_deref(), Some(&b"{\"kind\":\"B\"}"[..]));
+            assert_eq!(rec.headers, [("schema-id".to_string(), b"7".to_vec())]);
+        }
+        // The schema and its reference are fetched once.
+        assert_eq!(requests.load(Ordering::Relaxed), 2);
+        let mut unknown = Record {
+            value: Some(vec![0, 0, 0, 0, 8, 2]),
+            ..Default::default()
+        };
+        assert!(decoder.decode(&mut unknown).await.is_err());
+        let mut raw = Record {
+            value: Some(b"{}".to_vec()),
+            ..Default::default()
+        };
+        assert!(decoder.decode(&mut raw).await.is_err());
+        assert_eq!(raw.value.as_deref(), Some(&b"{}"[..]));
+    }
+}
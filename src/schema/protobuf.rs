@@ -0,0 +1,686 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use serde_json::{Map, Value};
+
+use super::bytes_to_json;
+use crate::state::codec::take;
+
+/// Well-known types that schemas import without registering them.
+const WELL_KNOWN: &str = r#"
+syntax = "proto3";
+package google.protobuf;
+message Timestamp { int64 seconds = 1; int32 nanos = 2; }
+message Duration { int64 seconds = 1; int32 nanos = 2; }
+message Empty {}
+message DoubleValue { double value = 1; }
+message FloatValue { float value = 1; }
+message Int64Value { int64 value = 1; }
+message UInt64Value { uint64 value = 1; }
+message Int32Value { int32 value = 1; }
+message UInt32Value { uint32 value = 1; }
+message BoolValue { bool value = 1; }
+message StringValue { string value = 1; }
+message BytesValue { bytes value = 1; }
+"#;
+
+/// Message and enum types of a Protobuf schema, parsed from the `.proto`
+/// source the Schema Registry stores. Options, services and extensions are
+/// ignored, and proto2 groups are not supported.
+#[derive(Debug, Default)]
+pub struct Schema {
+    /// Top-level messages of the schema in declaration order, which the
+    /// message indexes of the wire format refer to.
+    messages: Vec<String>,
+    types: HashMap<String, Message>,
+    enums: HashMap<String, HashMap<i32, String>>,
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    fields: HashMap<u32, Field>,
+    /// Nested messages in declaration order.
+    nested: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    kind: Kind,
+    repeated: bool,
+    /// Key and value kinds of map fields.
+    map: Option<Box<(Kind, Kind)>>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+    /// A message or enum, resolved from the message it is used in.
+    Named(String),
+}
+
+enum Resolved<'a> {
+    Message(&'a str),
+    Enum(&'a HashMap<i32, String>),
+}
+
+impl Schema {
+    /// Parses `schema` along with the schemas it imports, given as
+    /// `references`.
+    pub fn parse(schema: &str, references: &[String]) -> anyhow::Result<Self> {
+        let mut parsed = Self::default();
+        parsed.parse_file(WELL_KNOWN, false)?;
+        for reference in references {
+            parsed
+                .parse_file(reference, false)
+                .with_context(|| "Invalid referenced Protobuf schema")?;
+        }
+        parsed.parse_file(schema, true)?;
+        Ok(parsed)
+    }
+
+    /// Decodes a payload prefixed with the message indexes of the Confluent
+    /// wire format into the proto3 JSON mapping, keeping the field names of
+    /// the schema. Fields absent from the payload are left out.
+    pub fn decode(&self, mut buf: &[u8]) -> anyhow::Result<Value> {
+        let count = read_zigzag(&mut buf)?;
+        let indexes = match count {
+            0 => vec![0],
+            _ => (0..count)
+                .map(|_| read_zigzag(&mut buf))
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        };
+        let mut name = None;
+        for index in indexes {
+            let candidates = match name {
+                None => &self.messages,
+                Some(name) => &self.message(name)?.nested,
+            };
+            name = usize::try_from(index)
+                .ok()
+                .and_then(|i| candidates.get(i))
+                .map(String::as_str);
+            if name.is_none() {
+                bail!("Message index {index} out of range");
+            }
+        }
+        self.decode_message(name.unwrap_or_default(), buf)
+    }
+
+    fn parse_file(&mut self, source: &str, root: bool) -> anyhow::Result<()> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+        };
+        let mut package = String::new();
+        let mut messages = Vec::new();
+        while let Some(token) = parser.peek() {
+            match token {
+                "package" => {
+                    parser.next()?;
+                    package = parser.next()?.to_string();
+                    parser.expect(";")?;
+                }
+                "message" => {
+                    parser.next()?;
+                    self.parse_message(&mut parser, &package, &mut messages)?;
+                }
+                "enum" => {
+                    parser.next()?;
+                    self.parse_enum(&mut parser, &package)?;
+                }
+                ";" => {
+                    parser.next()?;
+                }
+                // syntax, import, option, service and extend
+                _ => parser.skip_statement()?,
+            }
+        }
+        if root {
+            self.messages = messages;
+        }
+        Ok(())
+    }
+
+    fn parse_message(
+        &mut self,
+        parser: &mut Parser,
+        scope: &str,
+        siblings: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        let name = qualify(scope, parser.next()?);
+        parser.expect("{")?;
+        let mut message = Message::default();
+        let mut in_oneof = false;
+        loop {
+            match parser
+                .peek()
+                .ok_or_else(|| anyhow!("Unterminated message {name}"))?
+            {
+                "}" if in_oneof => {
+                    parser.next()?;
+                    in_oneof = false;
+                }
+                "}" => {
+                    parser.next()?;
+                    break;
+                }
+                "message" => {
+                    parser.next()?;
+                    self.parse_message(parser, &name, &mut message.nested)?;
+                }
+                "enum" => {
+                    parser.next()?;
+                    self.parse_enum(parser, &name)?;
+                }
+                "oneof" => {
+                    parser.next()?;
+                    parser.next()?;
+                    parser.expect("{")?;
+                    in_oneof = true;
+                }
+                "option" | "reserved" | "extensions" | "extend" => parser.skip_statement()?,
+                ";" => {
+                    parser.next()?;
+                }
+                _ => {
+                    let (number, field) = parse_field(parser, &name)?;
+                    message.fields.insert(number, field);
+                }
+            }
+        }
+        siblings.push(name.clone());
+        self.types.insert(name, message);
+        Ok(())
+    }
+
+    fn parse_enum(&mut self, parser: &mut Parser, scope: &str) -> anyhow::Result<()> {
+        let name = qualify(scope, parser.next()?);
+        parser.expect("{")?;
+        let mut values = HashMap::new();
+        loop {
+            match parser
+                .peek()
+                .ok_or_else(|| anyhow!("Unterminated enum {name}"))?
+            {
+                "}" => {
+                    parser.next()?;
+                    break;
+                }
+                "option" | "reserved" => parser.skip_statement()?,
+                ";" => {
+                    parser.next()?;
+                }
+                _ => {
+                    let value = parser.next()?.to_string();
+                    parser.expect("=")?;
+                    let number = parser.next()?;
+                    let number: i32 = number
+                        .parse()
+                        .with_context(|| format!("Invalid number {number} in enum {name}"))?;
+                    parser.skip_options()?;
+                    parser.expect(";")?;
+                    values.entry(number).or_insert(value);
+                }
+            }
+        }
+        self.enums.insert(name, values);
+        Ok(())
+    }
+
+    fn message(&self, name: &str) -> anyhow::Result<&Message> {
+        self.types
+            .get(name)
+            .ok_or_else(|| anyhow!("Unknown message {name}"))
+    }
+
+    /// Resolves `name` as used in `scope`, searching from the innermost
+    /// scope outwards as protoc does.
+    fn resolve(&self, scope: &str, name: &str) -> anyhow::Result<Resolved<'_>> {
+        let lookup = |full: &str| {
+            if let Some((name, _)) = self.types.get_key_value(full) {
+                Some(Resolved::Message(name.as_str()))
+            } else {
+                self.enums.get(full).map(Resolved::Enum)
+            }
+        };
+        if let Some(full) = name.strip_prefix('.') {
+            return lookup(full).ok_or_else(|| anyhow!("Unknown type {name}"));
+        }
+        let mut scope = scope;
+        loop {
+            if let Some(resolved) = lookup(&qualify(scope, name)) {
+                return Ok(resolved);
+            }
+            if scope.is_empty() {
+                bail!("Unknown type {name}");
+            }
+            scope = scope.rsplit_once('.').map_or("", |(outer, _)| outer);
+        }
+    }
+
+    fn decode_message(&self, name: &str, mut buf: &[u8]) -> anyhow::Result<Value> {
+        let message = self.message(name)?;
+        let mut object = Map::new();
+        while !buf.is_empty() {
+            let tag = read_varint(&mut buf)?;
+            let (number, wire) = ((tag >> 3) as u32, (tag & 7) as u8);
+            let field = match message.fields.get(&number) {
+                Some(field) => field,
+                None => {
+                    skip(wire, &mut buf)?;
+                    continue;
+                }
+            };
+            if let Some(entry) = &field.map {
+                let (key, value) = self.decode_entry(entry, name, read_len(&mut buf)?)?;
+                if let Value::Object(map) = object
+                    .entry(field.name.clone())
+                    .or_insert_with(|| Value::Object(Map::new()))
+                {
+                    map.insert(key, value);
+                }
+                continue;
+            }
+            let natural = self.wire_type(&field.kind, name)?;
+            let values = if field.repeated && wire == 2 && natural != 2 {
+                let mut packed = read_len(&mut buf)?;
+                let mut values = Vec::new();
+                while !packed.is_empty() {
+                    values.push(self.decode_value(&field.kind, name, natural, &mut packed)?);
+                }
+                values
+            } else {
+                vec![self.decode_value(&field.kind, name, wire, &mut buf)?]
+            };
+            if field.repeated {
+                if let Value::Array(array) = object
+                    .entry(field.name.clone())
+                    .or_insert_with(|| Value::Array(Vec::new()))
+                {
+                    array.extend(values);
+                }
+            } else if let Some(value) = values.into_iter().next() {
+                object.insert(field.name.clone(), value);
+            }
+        }
+        Ok(Value::Object(object))
+    }
+
+    fn decode_entry(
+        &self,
+        entry: &(Kind, Kind),
+        scope: &str,
+        mut buf: &[u8],
+    ) -> anyhow::Result<(String, Value)> {
+        let (mut key, mut value) = (String::new(), Value::Null);
+        while !buf.is_empty() {
+            let tag = read_varint(&mut buf)?;
+            let wire = (tag & 7) as u8;
+            match tag >> 3 {
+                1 => {
+                    key = match self.decode_value(&entry.0, scope, wire, &mut buf)? {
+                        Value::String(s) => s,
+                        other => other.to_string(),
+                    }
+                }
+                2 => value = self.decode_value(&entry.1, scope, wire, &mut buf)?,
+                _ => skip(wire, &mut buf)?,
+            }
+        }
+        Ok((key, value))
+    }
+
+    fn wire_type(&self, kind: &Kind, scope: &str) -> anyhow::Result<u8> {
+        Ok(match kind {
+            Kind::Int32
+            | Kind::Int64
+            | Kind::Uint32
+            | Kind::Uint64
+            | Kind::Sint32
+            | Kind::Sint64
+            | Kind::Bool => 0,
+            Kind::Fixed64 | Kind::Sfixed64 | Kind::Double => 1,
+            Kind::Fixed32 | Kind::Sfixed32 | Kind::Float => 5,
+            Kind::String | Kind::Bytes => 2,
+            Kind::Named(name) => match self.resolve(scope, name)? {
+                Resolved::Message(_) => 2,
+                Resolved::Enum(_) => 0,
+            },
+        })
+    }
+
+    /// Decodes a single value. 64-bit integers become strings as in the
+    /// proto3 JSON mapping, and enums their name when it is known.
+    fn decode_value(
+        &self,
+        kind: &Kind,
+        scope: &str,
+        wire: u8,
+        buf: &mut &[u8],
+    ) -> anyhow::Result<Value> {
+        let expected = self.wire_type(kind, scope)?;
+        if wire != expected {
+            bail!("Wire type {wire} does not match {kind:?}");
+        }
+        Ok(match kind {
+            Kind::Int32 => Value::from(read_varint(buf)? as i32),
+            Kind::Int64 => Value::from((read_varint(buf)? as i64).to_string()),
+            Kind::Uint32 => Value::from(read_varint(buf)? as u32),
+            Kind::Uint64 => Value::from(read_varint(buf)?.to_string()),
+            Kind::Sint32 => Value::from(zigzag(read_varint(buf)?)),
+            Kind::Sint64 => Value::from(zigzag(read_varint(buf)?).to_string()),
+            Kind::Bool => Value::Bool(read_varint(buf)? != 0),
+            Kind::Fixed32 => Value::from(u32::from_le_bytes(take(buf, 4)?.try_into()?)),
+            Kind::Sfixed32 => Value::from(i32::from_le_bytes(take(buf, 4)?.try_into()?)),
+            Kind::Float => Value::from(f32::from_le_bytes(take(buf, 4)?.try_into()?)),
+            Kind::Fixed64 => Value::from(u64::from_le_bytes(take(buf, 8)?.try_into()?).to_string()),
+            Kind::Sfixed64 => {
+                Value::from(i64::from_le_bytes(take(buf, 8)?.try_into()?).to_string())
+            }
+            Kind::Double => Value::from(f64::from_le_bytes(take(buf, 8)?.try_into()?)),
+            Kind::String => Value::String(std::str::from_utf8(read_len(buf)?)?.to_string()),
+            Kind::Bytes => bytes_to_json(read_len(buf)?),
+            Kind::Named(name) => match self.resolve(scope, name)? {
+                Resolved::Message(message) => self.decode_message(message, read_len(buf)?)?,
+                Resolved::Enum(values) => {
+                    let number = read_varint(buf)? as i32;
+                    match values.get(&number) {
+                        Some(value) => Value::String(value.clone()),
+                        None => Value::from(number),
+                    }
+                }
+            },
+        })
+    }
+}
+
+fn parse_field(parser: &mut Parser, scope: &str) -> anyhow::Result<(u32, Field)> {
+    let mut repeated = false;
+    let mut map = None;
+    let mut kind = parser.next()?.to_string();
+    match kind.as_str() {
+        "repeated" => {
+            repeated = true;
+            kind = parser.next()?.to_string();
+        }
+        "optional" | "required" => kind = parser.next()?.to_string(),
+        "group" => bail!("Protobuf groups are not supported"),
+        "map" if parser.peek() == Some("<") => {
+            parser.expect("<")?;
+            let key = parse_kind(parser.next()?);
+            parser.expect(",")?;
+            let value = parse_kind(parser.next()?);
+            parser.expect(">")?;
+            map = Some(Box::new((key, value)));
+        }
+        _ => {}
+    }
+    let kind = parse_kind(&kind);
+    let name = parser.next()?.to_string();
+    parser.expect("=")?;
+    let number = parser.next()?;
+    let number: u32 = number
+        .parse()
+        .with_context(|| format!("Invalid number {number} of field {name} in {scope}"))?;
+    parser.skip_options()?;
+    parser.expect(";")?;
+    Ok((
+        number,
+        Field {
+            name,
+            kind,
+            repeated,
+            map,
+        },
+    ))
+}
+
+fn parse_kind(kind: &str) -> Kind {
+    match kind {
+        "double" => Kind::Double,
+        "float" => Kind::Float,
+        "int32" => Kind::Int32,
+        "int64" => Kind::Int64,
+        "uint32" => Kind::Uint32,
+        "uint64" => Kind::Uint64,
+        "sint32" => Kind::Sint32,
+        "sint64" => Kind::Sint64,
+        "fixed32" => Kind::Fixed32,
+        "fixed64" => Kind::Fixed64,
+        "sfixed32" => Kind::Sfixed32,
+        "sfixed64" => Kind::Sfixed64,
+        "bool" => Kind::Bool,
+        "string" => Kind::String,
+        "bytes" => Kind::Bytes,
+        name => Kind::Named(name.to_string()),
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{scope}.{name}")
+    }
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> anyhow::Result<&str> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Unexpected end of Protobuf schema"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> anyhow::Result<()> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => bail!("Expected {expected} but found {token} in Protobuf schema"),
+        }
+    }
+
+    /// Skips a statement up to its `;`, or a block up to its closing brace.
+    fn skip_statement(&mut self) -> anyhow::Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                ";" if depth == 0 => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Skips the `[...]` options of a field or enum value.
+    fn skip_options(&mut self) -> anyhow::Result<()> {
+        if self.peek() == Some("[") {
+            while self.next()? != "]" {}
+        }
+        Ok(())
+    }
+}
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+            }
+            '"' | '\'' => {
+                let mut token = c.to_string();
+                while let Some(next) = chars.next() {
+                    token.push(next);
+                    if next == '\\' {
+                        token.extend(chars.next());
+                    } else if next == c {
+                        break;
+                    }
+                }
+                tokens.push(token);
+            }
+            c if c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '+') => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_alphanumeric() || matches!(next, '_' | '.')) {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+            c => tokens.push(c.to_string()),
+        }
+    }
+    Ok(tokens)
+}
+
+fn read_varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut n: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = take(buf, 1)?[0];
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    bail!("Protobuf varint is too long")
+}
+
+fn zigzag(n: u64) -> i64 {
+    (n >> 1) as i64 ^ -((n & 1) as i64)
+}
+
+fn read_zigzag(buf: &mut &[u8]) -> anyhow::Result<i64> {
+    read_varint(buf).map(zigzag)
+}
+
+fn read_len<'a>(buf: &mut &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    let len = usize::try_from(read_varint(buf)?)?;
+    take(buf, len)
+}
+
+fn skip(wire: u8, buf: &mut &[u8]) -> anyhow::Result<()> {
+    match wire {
+        0 => {
+            read_varint(buf)?;
+        }
+        1 => {
+            take(buf, 8)?;
+        }
+        2 => {
+            read_len(buf)?;
+        }
+        5 => {
+            take(buf, 4)?;
+        }
+        _ => bail!("Unsupported wire type {wire}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_decode() {
+        let reference = r#"syntax = "proto3"; package shop; enum Kind { ONLINE = 0; STORE = 1; }"#;
+        let schema = r#"
+            syntax = "proto3";
+            package shop;
+            import "common.proto";
+            import "google/protobuf/timestamp.proto";
+
+            message Other { string x = 1; }
+            /* An order. */
+            message Order {
+                int64 id = 1 [deprecated = true];
+                Kind kind = 2;
+                repeated int32 qty = 3;
+                map<string, int32> stock = 4;
+                oneof detail {
+                    Line line = 5;
+                    google.protobuf.Timestamp at = 6;
+                }
+                message Line { string sku = 1; }
+            }
+        "#;
+        let schema = Schema::parse(schema, &[reference.to_string()]).unwrap();
+        let order = [
+            0x02, 0x02, // message indexes [1]
+            0x08, 0xac, 0x02, // id 300
+            0x10, 0x01, // STORE
+            0x1a, 0x02, 0x01, 0x02, // packed qty [1, 2]
+            0x22, 0x05, 0x0a, 0x01, b'a', 0x10, 0x05, // stock {"a": 5}
+            0x2a, 0x03, 0x0a, 0x01, b'z', // line {sku: "z"}
+            0x48, 0x01, // unknown field 9
+        ];
+        assert_eq!(
+            schema.decode(&order).unwrap(),
+            serde_json::json!({
+                "id": "300", "kind": "STORE", "qty": [1, 2], "stock": {"a": 5},
+                "line": {"sku": "z"}
+            })
+        );
+        // Nested Order.Line, then the first message.
+        let line = [0x04, 0x02, 0x00, 0x0a, 0x01, b'z'];
+        assert_eq!(
+            schema.decode(&line).unwrap(),
+            serde_json::json!({"sku": "z"})
+        );
+        assert_eq!(
+            schema.decode(&[0x00, 0x0a, 0x01, b'y']).unwrap(),
+            serde_json::json!({"x": "y"})
+        );
+        assert!(schema.decode(&[0x02, 0x06]).is_err());
+    }
+}
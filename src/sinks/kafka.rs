@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use rdkafka::{
+    message::{Header, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord, Producer},
+};
+
+use super::SinkLimits;
+use crate::conf;
+use crate::record::Record;
+use crate::sources::kafka::connection_config;
+use crate::sources::oauth::{self, ClusterContext};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Produces records to a Kafka topic, partitioned by key. The producer
+/// buffers and retries on its own, so each send resolves once the record is
+/// acknowledged or has failed for good.
+#[derive(Clone)]
+pub struct KafkaSink {
+    topic: String,
+    producer: FutureProducer<ClusterContext>,
+    limits: Arc<SinkLimits>,
+    /// Bytes of records waiting to be acknowledged.
+    queued: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for KafkaSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl KafkaSink {
+    pub fn new(
+        brokers: &[String],
+        topic: &str,
+        sasl: &conf::SaslConfig,
+        tls: Option<&conf::TlsConfig>,
+        rate_limit: Option<&conf::SinkRateLimits>,
+    ) -> anyhow::Result<Self> {
+        let producer: FutureProducer<ClusterContext> = connection_config(brokers, sasl, tls)
+            .set("enable.idempotence", "true")
+            .create_with_context(ClusterContext::new(sasl))
+            .with_context(|| format!("Failed to initialize producer for topic: {topic}"))?;
+        oauth::refresh_in_background(producer.client())?;
+        Ok(Self {
+            topic: topic.to_string(),
+            producer,
+            limits: Arc::new(SinkLimits::new(rate_limit)?),
+            queued: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn limits(&self) -> &SinkLimits {
+        &self.limits
+    }
+
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub async fn send(&self, rec: &Record) -> anyhow::Result<()> {
+        let size = rec.key.as_ref().map_or(0, Vec::len) + rec.value.as_ref().map_or(0, Vec::len);
+        self.queued.fetch_add(size as u64, Ordering::Relaxed);
+        self.limits.bytes(size).await;
+        self.limits.request().await;
+        let headers = rec.headers.iter().fold(OwnedHeaders::new(), |h, (k, v)| {
+            h.insert(Header {
+                key: k,
+                value: Some(v),
+            })
+        });
+        let mut record: FutureRecord<'_, [u8], [u8]> =
+            FutureRecord::to(&self.topic).headers(headers);
+        if let Some(key) = &rec.key {
+            record = record.key(key);
+        }
+        if let Some(value) = &rec.value {
+            record = record.payload(value);
+        }
+        if rec.timestamp >= 0 {
+            record = record.timestamp(rec.timestamp);
+        }
+        let res = self.producer.send(record, TIMEOUT).await;
+        self.queued.fetch_sub(size as u64, Ordering::Relaxed);
+        res.map_err(|(e, _)| anyhow!(e))
+            .with_context(|| format!("Could not write to topic {}", self.topic))?;
+        Ok(())
+    }
+}
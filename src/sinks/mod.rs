@@ -1 +1,312 @@
+pub mod kafka;
 pub mod s3;
+pub mod stdout;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use anyhow::bail;
+use tracing::error;
+
+use crate::component::{s3_sink, sinks, EmittedRecord, Status};
+use crate::conf;
+use crate::ratelimit::TokenBucket;
+use crate::record::Record;
+
+use self::kafka::KafkaSink;
+use self::s3::BufferedS3Sink;
+use self::stdout::StdoutSink;
+
+#[derive(Clone, Debug)]
+enum Sink {
+    S3(BufferedS3Sink),
+    Kafka(KafkaSink),
+    Stdout(StdoutSink),
+}
+
+/// The named sinks of a flow and the routes that send records to them. Each
+/// sink buffers on its own, and a failing sink does not keep records from
+/// reaching the others.
+#[derive(Clone, Debug, Default)]
+pub struct Sinks {
+    sinks: Arc<BTreeMap<String, (Sink, SinkStats)>>,
+    routes: Arc<Vec<conf::Route>>,
+}
+
+/// Rate limits of a sink, applied by waiting before each write and request.
+#[derive(Debug, Default)]
+pub struct SinkLimits {
+    pub bytes: Option<TokenBucket>,
+    pub requests: Option<TokenBucket>,
+}
+
+impl SinkLimits {
+    pub fn new(cfg: Option<&conf::SinkRateLimits>) -> anyhow::Result<Self> {
+        let cfg = match cfg {
+            Some(cfg) => cfg,
+            None => return Ok(Self::default()),
+        };
+        Ok(Self {
+            bytes: cfg.bytes.as_ref().map(TokenBucket::new).transpose()?,
+            requests: cfg.requests.as_ref().map(TokenBucket::new).transpose()?,
+        })
+    }
+
+    pub async fn bytes(&self, n: usize) {
+        if let Some(bytes) = &self.bytes {
+            bytes.acquire(n as u64).await;
+        }
+    }
+
+    pub async fn request(&self) {
+        if let Some(requests) = &self.requests {
+            requests.acquire(1).await;
+        }
+    }
+}
+
+/// Writes to a sink by outcome.
+#[derive(Debug, Default)]
+pub struct SinkStats {
+    pub ok: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+impl Sinks {
+    pub async fn new(cfg: &[conf::Sink], routes: &[conf::Route]) -> anyhow::Result<Self> {
+        let mut sinks = BTreeMap::new();
+        for sink in cfg {
+            let name = sink.name();
+            let created = match sink {
+                conf::Sink::None => continue,
+                conf::Sink::S3 { .. } => Sink::S3(BufferedS3Sink::new(sink).await?),
+                conf::Sink::Kafka {
+                    brokers,
+                    topic,
+                    sasl,
+                    tls,
+                    rate_limit,
+                    ..
+                } => Sink::Kafka(KafkaSink::new(
+                    brokers,
+                    topic,
+                    sasl,
+                    tls.as_ref(),
+                    rate_limit.as_ref(),
+                )?),
+                conf::Sink::Stdout { format, .. } => Sink::Stdout(StdoutSink::new(*format)),
+            };
+            if sinks
+                .insert(name.clone(), (created, SinkStats::default()))
+                .is_some()
+            {
+                bail!("Sink name {name} is used more than once");
+            }
+        }
+        for route in routes {
+            if let Some(name) = route.sinks.iter().find(|s| !sinks.contains_key(*s)) {
+                bail!("Route to unknown sink {name}");
+            }
+        }
+        Ok(Self {
+            sinks: Arc::new(sinks),
+            routes: Arc::new(routes.to_vec()),
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.sinks.contains_key(name)
+    }
+
+    /// Writes by sink name.
+    pub fn stats(&self) -> impl Iterator<Item = (&str, &SinkStats)> {
+        self.sinks
+            .iter()
+            .map(|(name, (_, stats))| (name.as_str(), stats))
+    }
+
+    /// Rate limiters by sink name and limited quantity.
+    pub fn limiters(&self) -> impl Iterator<Item = (&str, &'static str, &TokenBucket)> {
+        self.sinks.iter().flat_map(|(name, (sink, _))| {
+            let limits = match sink {
+                Sink::S3(s3) => Some(s3.limits()),
+                Sink::Kafka(kafka) => Some(kafka.limits()),
+                Sink::Stdout(_) => None,
+            };
+            limits
+                .into_iter()
+                .flat_map(|limits| [("bytes", &limits.bytes), ("requests", &limits.requests)])
+                .filter_map(move |(kind, l)| l.as_ref().map(|l| (name.as_str(), kind, l)))
+        })
+    }
+
+    /// Bytes waiting to be written by sink name.
+    pub fn queued(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.sinks.iter().map(|(name, (sink, _))| {
+            let queued = match sink {
+                Sink::S3(s3) => s3.queued_bytes(),
+                Sink::Kafka(kafka) => kafka.queued_bytes(),
+                Sink::Stdout(_) => 0,
+            };
+            (name.as_str(), queued)
+        })
+    }
+
+    /// Sends `rec` to the sink called `name`. S3 sinks write the value to the
+    /// buffer of the record's partition.
+    pub async fn send(&self, name: &str, rec: &Record) -> Status {
+        let (sink, stats) = match self.sinks.get(name) {
+            Some(sink) => sink,
+            None => {
+                error!(unknown_sink=%name);
+                return Status::Error;
+            }
+        };
+        let status = match sink {
+            Sink::S3(s3) => {
+                let body = rec.value.as_deref().unwrap_or_default();
+                s3.clone().write_partition(rec.partition, body).await
+            }
+            Sink::Kafka(kafka) => match kafka.send(rec).await {
+                Ok(()) => Status::Ok,
+                Err(e) => {
+                    error!(sink=%name, kafka_sink_error=?e);
+                    Status::Error
+                }
+            },
+            Sink::Stdout(stdout) => match stdout.send(rec).await {
+                Ok(()) => Status::Ok,
+                Err(e) => {
+                    error!(sink=%name, stdout_sink_error=?e);
+                    Status::Error
+                }
+            },
+        };
+        match status {
+            Status::Ok => stats.ok.fetch_add(1, Ordering::Relaxed),
+            Status::Error => stats.failed.fetch_add(1, Ordering::Relaxed),
+        };
+        status
+    }
+
+    /// Sends each of `records` to the sinks of every route it matches.
+    pub async fn route(&self, records: &[Record]) -> Status {
+        let mut status = Status::Ok;
+        for rec in records {
+            for route in self.routes.iter().filter(|r| matches(r, rec)) {
+                for name in &route.sinks {
+                    if self.send(name, rec).await == Status::Error {
+                        status = Status::Error;
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    /// Writes out what sinks buffer for `partitions`. Kafka sinks wait for
+    /// each record to be delivered, so only S3 sinks hold records back.
+    pub async fn flush(&self, partitions: &[i32]) -> Status {
+        let mut status = Status::Ok;
+        for (name, (sink, _)) in self.sinks.iter() {
+            if let Sink::S3(s3) = sink {
+                if s3.flush_partitions(partitions).await == Status::Error {
+                    error!(sink=%name, flush = "failed");
+                    status = Status::Error;
+                }
+            }
+        }
+        status
+    }
+
+    /// Writes to the first S3 sink, which backs the `s3-sink` interface.
+    pub async fn write_s3(&self, partition: i32, body: &[u8]) -> Status {
+        let s3 = self.sinks.values().find_map(|(sink, _)| match sink {
+            Sink::S3(s3) => Some(s3),
+            Sink::Kafka(_) | Sink::Stdout(_) => None,
+        });
+        match s3 {
+            Some(s3) => s3.clone().write_partition(partition, body).await,
+            None => {
+                error!(s3_sink = "not configured");
+                Status::Error
+            }
+        }
+    }
+}
+
+fn matches(route: &conf::Route, rec: &Record) -> bool {
+    let topic = route.topic.as_ref().is_none_or(|t| *t == rec.topic);
+    let key = route.key_prefix.as_ref().is_none_or(|p| {
+        rec.key
+            .as_deref()
+            .is_some_and(|k| k.starts_with(p.as_bytes()))
+    });
+    let header = route.header.as_ref().is_none_or(|h| {
+        rec.headers.iter().any(|(k, v)| {
+            *k == h.name && h.value.as_ref().is_none_or(|value| value.as_bytes() == v)
+        })
+    });
+    topic && key && header
+}
+
+/// Sinks as seen by a guest processing a record of `partition`.
+#[derive(Clone, Debug)]
+pub struct SinkRouter {
+    pub sinks: Sinks,
+    pub partition: i32,
+}
+
+impl s3_sink::Host for SinkRouter {
+    async fn write(&mut self, partition: i32, body: Vec<u8>) -> Status {
+        self.sinks.write_s3(partition, &body).await
+    }
+}
+
+impl sinks::Host for SinkRouter {
+    async fn send(&mut self, name: String, rec: EmittedRecord) -> Status {
+        let rec = Record {
+            key: rec.key,
+            value: rec.value,
+            headers: rec.headers,
+            partition: self.partition,
+            ..Default::default()
+        };
+        self.sinks.send(&name, &rec).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_route_matches() {
+        let rec = Record {
+            key: Some(b"eu/123".to_vec()),
+            headers: vec![("type".to_string(), b"order".to_vec())],
+            topic: "orders".to_string(),
+            ..Default::default()
+        };
+        let route = |topic: Option<&str>, key_prefix: Option<&str>, header| conf::Route {
+            sinks: vec!["archive".to_string()],
+            topic: topic.map(str::to_string),
+            key_prefix: key_prefix.map(str::to_string),
+            header,
+        };
+        let header = |value: &str| {
+            Some(conf::HeaderMatch {
+                name: "type".to_string(),
+                value: Some(value.to_string()),
+            })
+        };
+        assert!(matches(&route(None, None, None), &rec));
+        assert!(matches(
+            &route(Some("orders"), Some("eu/"), header("order")),
+            &rec
+        ));
+        assert!(!matches(&route(Some("payments"), None, None), &rec));
+        assert!(!matches(&route(None, Some("us/"), None), &rec));
+        assert!(!matches(&route(None, None, header("refund")), &rec));
+    }
+}
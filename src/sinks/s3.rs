@@ -1,28 +1,47 @@
+use super::SinkLimits;
+use crate::component::Status;
 use crate::conf;
 use anyhow::anyhow;
-use async_trait::async_trait;
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::{
     types::{ByteStream, SdkError},
     Client, Region,
 };
 use bytes::Bytes;
+use futures::future::{BoxFuture, FutureExt, Shared};
 use std::{
     collections::BTreeMap,
     ops::DerefMut,
+    sync::atomic::{AtomicU64, Ordering},
     sync::{Arc, Mutex},
 };
 use tracing::{debug, error, warn};
 
-wit_bindgen_wasmtime::export!({ paths: ["wit/s3-sink.wit"], async: * });
+/// Creates a client for `region`, falling back to the default provider chain.
+pub async fn create_client(region: &str) -> Client {
+    let region_provider =
+        RegionProviderChain::first_try(Region::new(region.to_string())).or_default_provider();
+    let shared_config = aws_config::from_env().region(region_provider).load().await;
+    Client::new(&shared_config)
+}
+
+/// Upload of a buffer, driven by whoever waits for it.
+type Upload = Shared<BoxFuture<'static, Status>>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct BufferedS3Sink {
     bucket: String,
     key_prefix: String,
     file_size: usize,
     client: Client,
     buffer: Arc<Mutex<BTreeMap<i32, Vec<u8>>>>,
+    limits: Arc<SinkLimits>,
+    /// Bytes being uploaded. Buffered bytes are left out, as they are only
+    /// uploaded once more records come in.
+    queued: Arc<AtomicU64>,
+    /// Uploads by partition, so that flushes can wait for the buffers
+    /// already taken to be uploaded.
+    uploads: Arc<Mutex<BTreeMap<i32, Vec<Upload>>>>,
 }
 
 impl BufferedS3Sink {
@@ -33,35 +52,44 @@ impl BufferedS3Sink {
                 bucket,
                 key_prefix,
                 file_size,
+                rate_limit,
+                ..
             } => {
-                let region_provider =
-                    RegionProviderChain::first_try(Region::new(region.to_string()))
-                        .or_default_provider();
-                let shared_config = aws_config::from_env().region(region_provider).load().await;
-                let client = Client::new(&shared_config);
+                let client = create_client(region).await;
                 Ok(Self {
                     bucket: bucket.to_string(),
                     key_prefix: key_prefix.to_string(),
                     file_size: usize::from(*file_size),
                     client,
                     buffer: Arc::new(Mutex::new(BTreeMap::new())),
+                    limits: Arc::new(SinkLimits::new(rate_limit.as_ref())?),
+                    queued: Arc::new(AtomicU64::new(0)),
+                    uploads: Arc::new(Mutex::new(BTreeMap::new())),
                 })
             }
-            conf::Sink::None => Err(anyhow!("Cannot create S3Writer when sink is None")),
+            _ => Err(anyhow!("Cannot create S3Writer for sink {}", cfg.name())),
         }
     }
-}
 
-#[async_trait]
-impl s3_sink::S3Sink for BufferedS3Sink {
-    async fn write(&mut self, partition_id: i32, body: &[u8]) -> s3_sink::Status {
-        let mut flush_buffer: Option<Vec<u8>> = None;
+    pub fn limits(&self) -> &SinkLimits {
+        &self.limits
+    }
+
+    pub fn queued_bytes(&self) -> u64 {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Appends `body` to the buffer for `partition_id`, uploading the buffer
+    /// to S3 once it is close to `file_size`.
+    pub async fn write_partition(&mut self, partition_id: i32, body: &[u8]) -> Status {
+        self.limits.bytes(body.len()).await;
+        let mut upload: Option<Upload> = None;
         {
             let l = self.buffer.lock();
             match l {
                 Err(e) => {
                     error!(s3_sink=%e);
-                    return s3_sink::Status::Error;
+                    return Status::Error;
                 }
                 Ok(mut g) => {
                     let m = g.deref_mut();
@@ -70,60 +98,131 @@ impl s3_sink::S3Sink for BufferedS3Sink {
                         .or_insert_with(|| Vec::with_capacity(self.file_size));
                     buf.extend_from_slice(body);
                     if buf.len() > ((0.8 * self.file_size as f32) as usize) {
-                        flush_buffer = m.insert(partition_id, Vec::with_capacity(self.file_size));
+                        upload = m
+                            .insert(partition_id, Vec::with_capacity(self.file_size))
+                            .map(|buf| self.upload_later(partition_id, buf));
                     }
                 }
             }
         }
 
-        match flush_buffer {
-            Some(buf) => {
-                let timestamp = chrono::Local::now();
-                let key = format!(
-                    "{}/{}/{}/{}",
-                    self.key_prefix,
-                    partition_id,
-                    timestamp.format("%Y/%m/%d/%H/%M/%S"),
-                    uuid::Uuid::new_v4()
-                );
-                debug!(s3_key=%key);
-                let client = &self.client;
-                let bucket = self.bucket.as_str();
-                let bytes = &Bytes::from(buf);
-                let key = key.as_str();
-                let resp =
-                    backoff::future::retry(backoff::ExponentialBackoff::default(), || async move {
-                        let resp = client
-                            .put_object()
-                            .bucket(bucket.to_string())
-                            .key(key.to_string())
-                            .body(ByteStream::from(bytes.clone()))
-                            .send()
-                            .await;
-                        match resp {
-                            Ok(r) => Ok(r),
-                            Err(e) => match &e {
-                                SdkError::ConstructionFailure(_b) => {
-                                    Err(backoff::Error::permanent(e))
-                                }
-                                _ => {
-                                    warn!(aws_sdk_error=%e);
-                                    Err(backoff::Error::transient(e))
-                                }
-                            },
-                        }
-                    })
-                    .await;
-                debug!(resp=?resp);
-                match resp {
-                    Ok(_p) => s3_sink::Status::Ok,
+        match upload {
+            Some(upload) => upload.await,
+            None => Status::Ok,
+        }
+    }
+
+    /// Uploads what is buffered for `partitions` and waits for their
+    /// uploads, including those already running, so that everything written
+    /// to them before is in S3 unless this fails.
+    pub async fn flush_partitions(&self, partitions: &[i32]) -> Status {
+        let uploads: Vec<Upload> = match self.buffer.lock() {
+            Err(e) => {
+                error!(s3_sink=%e);
+                return Status::Error;
+            }
+            Ok(mut g) => {
+                let mut uploads: Vec<Upload> = match self.uploads.lock() {
                     Err(e) => {
-                        error!(s3_sink_error=?e);
-                        s3_sink::Status::Error
+                        error!(s3_sink=%e);
+                        return Status::Error;
+                    }
+                    Ok(running) => partitions
+                        .iter()
+                        .filter_map(|p| running.get(p))
+                        .flatten()
+                        .filter(|u| u.peek().is_none())
+                        .cloned()
+                        .collect(),
+                };
+                for p in partitions {
+                    if let Some(buf) = g.remove(p).filter(|buf| !buf.is_empty()) {
+                        uploads.push(self.upload_later(*p, buf));
                     }
                 }
+                uploads
+            }
+        };
+        let mut status = Status::Ok;
+        for upload in uploads {
+            if upload.await == Status::Error {
+                status = Status::Error;
             }
-            None => s3_sink::Status::Ok,
         }
+        status
+    }
+
+    /// Tracks the upload of `buf`, which runs once waited for. It is called
+    /// with the buffer locked, so that a flush sees what was written either
+    /// buffered or uploading.
+    fn upload_later(&self, partition_id: i32, buf: Vec<u8>) -> Upload {
+        let sink = self.clone();
+        let upload = async move { sink.upload(partition_id, buf).await }
+            .boxed()
+            .shared();
+        if let Ok(mut uploads) = self.uploads.lock() {
+            let running = uploads.entry(partition_id).or_default();
+            running.retain(|u| u.peek().is_none());
+            running.push(upload.clone());
+        }
+        upload
+    }
+
+    async fn upload(&self, partition_id: i32, buf: Vec<u8>) -> Status {
+        let timestamp = chrono::Local::now();
+        let key = format!(
+            "{}/{}/{}/{}",
+            self.key_prefix,
+            partition_id,
+            timestamp.format("%Y/%m/%d/%H/%M/%S"),
+            uuid::Uuid::new_v4()
+        );
+        debug!(s3_key=%key);
+        let client = &self.client;
+        let bucket = self.bucket.as_str();
+        let bytes = &Bytes::from(buf);
+        self.queued.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        let key = key.as_str();
+        let limits = &self.limits;
+        let resp = backoff::future::retry(backoff::ExponentialBackoff::default(), || async move {
+            limits.request().await;
+            let resp = client
+                .put_object()
+                .bucket(bucket.to_string())
+                .key(key.to_string())
+                .body(ByteStream::from(bytes.clone()))
+                .send()
+                .await;
+            match resp {
+                Ok(r) => Ok(r),
+                Err(e) => match &e {
+                    SdkError::ConstructionFailure(_b) => Err(backoff::Error::permanent(e)),
+                    _ => {
+                        warn!(aws_sdk_error=%e);
+                        Err(backoff::Error::transient(e))
+                    }
+                },
+            }
+        })
+        .await;
+        self.queued.fetch_sub(bytes.len() as u64, Ordering::Relaxed);
+        debug!(resp=?resp);
+        match resp {
+            Ok(_p) => Status::Ok,
+            Err(e) => {
+                error!(s3_sink_error=?e);
+                Status::Error
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for BufferedS3Sink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferedS3Sink")
+            .field("bucket", &self.bucket)
+            .field("key_prefix", &self.key_prefix)
+            .field("file_size", &self.file_size)
+            .finish_non_exhaustive()
     }
 }
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio::io::{AsyncWriteExt, Stdout};
+use tokio::sync::Mutex;
+
+use crate::conf::LineFormat;
+use crate::record::Record;
+
+/// Writes records as lines to standard output, flushing after each so that
+/// output can be followed by the next command of a pipe.
+#[derive(Clone, Debug)]
+pub struct StdoutSink {
+    format: LineFormat,
+    stdout: Arc<Mutex<Stdout>>,
+}
+
+impl StdoutSink {
+    pub fn new(format: LineFormat) -> Self {
+        Self {
+            format,
+            stdout: Arc::new(Mutex::new(tokio::io::stdout())),
+        }
+    }
+
+    pub async fn send(&self, rec: &Record) -> anyhow::Result<()> {
+        let mut line = rec.to_line(self.format)?;
+        line.push(b'\n');
+        let mut stdout = self.stdout.lock().await;
+        stdout
+            .write_all(&line)
+            .await
+            .with_context(|| "Could not write to stdout")?;
+        stdout.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use super::PARTITION;
+use crate::conf;
+use crate::record::Record;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Lines read ahead of the flow before reading blocks.
+const READ_AHEAD: usize = 1024;
+
+/// Reads newline-delimited records from files matching a set of paths or
+/// glob patterns. The position of each file is the start of its first line
+/// not yet processed, so lines processed out of order are not skipped when
+/// the flow restarts.
+#[derive(Clone, Debug)]
+pub struct FileSource {
+    patterns: Vec<String>,
+    follow: bool,
+    poll_interval: Duration,
+    state_file: Option<PathBuf>,
+    files: Arc<Mutex<BTreeMap<String, Tail>>>,
+    paused: Arc<AtomicBool>,
+}
+
+/// Position of a file, which is reset when the file at the path is replaced.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Tail {
+    /// Inode of the file the position belongs to.
+    file_id: u64,
+    /// End of the last line read.
+    offset: u64,
+    /// Starts of the lines read and not yet processed.
+    #[serde(skip)]
+    pending: BTreeSet<u64>,
+}
+
+impl Tail {
+    fn committed(&self) -> u64 {
+        self.pending.first().copied().unwrap_or(self.offset)
+    }
+}
+
+/// A line being processed, which no longer holds back the position of its
+/// file once dropped.
+pub struct Line {
+    files: Arc<Mutex<BTreeMap<String, Tail>>>,
+    path: String,
+    offset: u64,
+}
+
+impl Drop for Line {
+    fn drop(&mut self) {
+        if let Ok(mut files) = self.files.lock() {
+            if let Some(tail) = files.get_mut(&self.path) {
+                tail.pending.remove(&self.offset);
+            }
+        }
+    }
+}
+
+/// A file being read.
+struct Reader {
+    reader: BufReader<File>,
+    file_id: u64,
+    /// Start of the line being read.
+    offset: u64,
+    /// Bytes of a line not yet terminated by a newline.
+    partial: Vec<u8>,
+}
+
+impl FileSource {
+    pub fn new(source: &conf::Source) -> anyhow::Result<Self> {
+        let conf::Source::File {
+            paths,
+            follow,
+            state_file,
+            poll_interval_ms,
+        } = source
+        else {
+            bail!("Not a file source");
+        };
+        if paths.is_empty() {
+            bail!("File sources must name at least one path");
+        }
+        for path in paths {
+            glob::Pattern::new(path).with_context(|| format!("Invalid file path: {path}"))?;
+        }
+        let files = match state_file {
+            Some(path) if path.exists() => {
+                let json = fs::read(path)
+                    .with_context(|| format!("Could not read file positions: {path:?}"))?;
+                serde_json::from_slice(&json)
+                    .with_context(|| format!("Invalid file positions: {path:?}"))?
+            }
+            _ => BTreeMap::new(),
+        };
+        info!(file_positions=?files);
+        Ok(Self {
+            patterns: paths.clone(),
+            follow: *follow,
+            poll_interval: poll_interval_ms.map_or(POLL_INTERVAL, Duration::from_millis),
+            state_file: state_file.clone(),
+            files: Arc::new(Mutex::new(files)),
+            paused: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Lines of the files, starting at their saved positions. The stream
+    /// ends once every file is read unless the source follows them.
+    pub fn records(&self) -> impl Stream<Item = anyhow::Result<Record>> {
+        let (tx, rx) = mpsc::channel(READ_AHEAD);
+        let source = self.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = source.read(&tx) {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        futures::stream::unfold(
+            rx,
+            |mut rx| async move { rx.recv().await.map(|rec| (rec, rx)) },
+        )
+    }
+
+    /// Tracks the line of `rec` until the returned value is dropped.
+    pub fn track(&self, rec: &Record) -> Line {
+        Line {
+            files: self.files.clone(),
+            path: rec.topic.clone(),
+            offset: rec.offset as u64,
+        }
+    }
+
+    /// Stops or resumes reading new lines.
+    pub fn pause(&self, pause: bool) {
+        self.paused.store(pause, Ordering::Relaxed);
+    }
+
+    /// Writes the position of every file to the state file, if configured.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let path = match &self.state_file {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let positions: BTreeMap<String, Tail> = self
+            .files()?
+            .iter()
+            .map(|(file, tail)| {
+                let tail = Tail {
+                    file_id: tail.file_id,
+                    offset: tail.committed(),
+                    pending: BTreeSet::new(),
+                };
+                (file.clone(), tail)
+            })
+            .collect();
+        let json = serde_json::to_vec(&positions)?;
+        // Written to a temporary file first so that a crash never leaves
+        // partially written positions behind.
+        let tmp = path.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+        fs::write(&tmp, json).with_context(|| format!("Could not write {tmp:?}"))?;
+        fs::rename(&tmp, path).with_context(|| format!("Could not rename {tmp:?}"))?;
+        Ok(())
+    }
+
+    fn read(&self, tx: &mpsc::Sender<anyhow::Result<Record>>) -> anyhow::Result<()> {
+        let mut readers: BTreeMap<String, Reader> = BTreeMap::new();
+        loop {
+            let matching = self.matching()?;
+            self.open_matching(&matching, &mut readers)?;
+            for (path, reader) in readers.iter_mut() {
+                if !self.read_lines(path, reader, tx)? {
+                    // The flow has stopped.
+                    return Ok(());
+                }
+                if self.follow {
+                    self.check_rotated(path, reader)?;
+                }
+            }
+            if !self.follow {
+                return Ok(());
+            }
+            self.forget_vanished(&matching, &mut readers)?;
+            std::thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// Files matching the patterns, in path order.
+    fn matching(&self) -> anyhow::Result<BTreeSet<String>> {
+        let mut paths = BTreeSet::new();
+        for pattern in &self.patterns {
+            for entry in glob::glob(pattern)? {
+                let path = entry?;
+                if path.is_file() {
+                    paths.insert(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Opens the files of `matching` that have no reader yet. Files deleted
+    /// since they matched are skipped, and forgotten with those that no
+    /// longer match.
+    fn open_matching(
+        &self,
+        matching: &BTreeSet<String>,
+        readers: &mut BTreeMap<String, Reader>,
+    ) -> anyhow::Result<()> {
+        for path in matching {
+            if !readers.contains_key(path) {
+                if let Some(reader) = self.open(path)? {
+                    readers.insert(path.clone(), reader);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens `path` at its saved position, or at the start when the file
+    /// was replaced or truncated since. Returns `None` if the file is gone.
+    fn open(&self, path: &str) -> anyhow::Result<Option<Reader>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!(file_gone = path);
+                return Ok(None);
+            }
+            Err(e) => return Err(e).with_context(|| format!("Could not open {path}")),
+        };
+        let metadata = file.metadata()?;
+        let mut files = self.files()?;
+        let tail = files.entry(path.to_string()).or_default();
+        if tail.file_id != metadata.ino() || tail.offset > metadata.len() {
+            *tail = Tail {
+                file_id: metadata.ino(),
+                ..Default::default()
+            };
+        }
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(tail.offset))?;
+        info!(file_opened = path, offset = tail.offset);
+        Ok(Some(Reader {
+            reader,
+            file_id: tail.file_id,
+            offset: tail.offset,
+            partial: Vec::new(),
+        }))
+    }
+
+    /// Sends the lines of `reader` up to the end of the file, returning
+    /// false once the flow stopped receiving them. An unterminated last line
+    /// is kept for later when following the file.
+    fn read_lines(
+        &self,
+        path: &str,
+        reader: &mut Reader,
+        tx: &mpsc::Sender<anyhow::Result<Record>>,
+    ) -> anyhow::Result<bool> {
+        loop {
+            while self.paused.load(Ordering::Relaxed) {
+                std::thread::sleep(self.poll_interval);
+            }
+            let read = reader
+                .reader
+                .read_until(b'\n', &mut reader.partial)
+                .with_context(|| format!("Could not read {path}"))?;
+            let terminated = reader.partial.last() == Some(&b'\n');
+            if !terminated && (self.follow || read == 0) {
+                return Ok(true);
+            }
+            let mut value = std::mem::take(&mut reader.partial);
+            let len = value.len() as u64;
+            if terminated {
+                value.pop();
+                if value.last() == Some(&b'\r') {
+                    value.pop();
+                }
+            }
+            let rec = Record {
+                value: Some(value),
+                topic: path.to_string(),
+                partition: PARTITION,
+                offset: reader.offset as i64,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                ..Default::default()
+            };
+            if let Some(tail) = self.files()?.get_mut(path) {
+                tail.pending.insert(reader.offset);
+                tail.offset = reader.offset + len;
+            }
+            reader.offset += len;
+            if tx.blocking_send(Ok(rec)).is_err() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Reopens `path` from the start once the file at the path was replaced
+    /// or truncated, after every line read from the previous one has been
+    /// processed so that its position is not lost.
+    fn check_rotated(&self, path: &str, reader: &mut Reader) -> anyhow::Result<()> {
+        let metadata = match fs::metadata(Path::new(path)) {
+            Ok(m) => m,
+            // Moved away and not recreated yet.
+            Err(_) => return Ok(()),
+        };
+        if metadata.ino() == reader.file_id && metadata.len() >= reader.offset {
+            return Ok(());
+        }
+        let pending = self.files()?.get(path).map_or(0, |t| t.pending.len());
+        if pending > 0 {
+            return Ok(());
+        }
+        if !reader.partial.is_empty() {
+            warn!(
+                file_rotated = path,
+                dropped_partial_line = reader.partial.len()
+            );
+        }
+        if let Some(tail) = self.files()?.get_mut(path) {
+            // Forces the position to be reset for the new file.
+            tail.file_id = 0;
+        }
+        // Moved away again since, in which case the file is forgotten once
+        // it no longer matches.
+        if let Some(reopened) = self.open(path)? {
+            *reader = reopened;
+        }
+        Ok(())
+    }
+
+    /// Closes the files that no longer match the patterns, such as deleted
+    /// or rotated logs, and forgets their positions once every line read
+    /// from them has been processed.
+    fn forget_vanished(
+        &self,
+        matching: &BTreeSet<String>,
+        readers: &mut BTreeMap<String, Reader>,
+    ) -> anyhow::Result<()> {
+        let mut files = self.files()?;
+        files.retain(|path, tail| matching.contains(path) || !tail.pending.is_empty());
+        readers.retain(|path, reader| {
+            if files.contains_key(path) {
+                return true;
+            }
+            if !reader.partial.is_empty() {
+                warn!(
+                    file_vanished = path,
+                    dropped_partial_line = reader.partial.len()
+                );
+            }
+            info!(file_closed = path);
+            false
+        });
+        Ok(())
+    }
+
+    fn files(&self) -> anyhow::Result<MutexGuard<'_, BTreeMap<String, Tail>>> {
+        self.files
+            .lock()
+            .map_err(|_| anyhow!("File positions lock poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use std::io::Write;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_records() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("a.log");
+        fs::write(&log, "first\r\nsecond\nlast").unwrap();
+        fs::write(dir.join("b.txt"), "ignored\n").unwrap();
+        let source = conf::Source::File {
+            paths: vec![dir.join("*.log").to_string_lossy().into_owned()],
+            follow: false,
+            state_file: Some(dir.join("positions.json")),
+            poll_interval_ms: None,
+        };
+        let files = FileSource::new(&source).unwrap();
+        let records: Vec<Record> = files.records().try_collect().await.unwrap();
+        let lines: Vec<(&[u8], i64)> = records
+            .iter()
+            .map(|r| (r.value.as_deref().unwrap_or_default(), r.offset))
+            .collect();
+        assert_eq!(
+            lines,
+            [(&b"first"[..], 0), (&b"second"[..], 7), (&b"last"[..], 14)]
+        );
+        assert_eq!(records[0].topic, log.to_string_lossy());
+        // The second line is still being processed when positions are saved.
+        let _second = files.track(&records[1]);
+        drop(files.track(&records[0]));
+        drop(files.track(&records[2]));
+        files.save().unwrap();
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&log)
+            .unwrap()
+            .write_all(b"\nappended\n")
+            .unwrap();
+        let files = FileSource::new(&source).unwrap();
+        let records: Vec<Record> = files.records().try_collect().await.unwrap();
+        let offsets: Vec<i64> = records.iter().map(|r| r.offset).collect();
+        assert_eq!(offsets, [7, 14, 19]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_forget_vanished() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("a.log");
+        fs::write(&log, "line\n").unwrap();
+        let files = FileSource::new(&conf::Source::File {
+            paths: vec![dir.join("*.log").to_string_lossy().into_owned()],
+            follow: true,
+            state_file: None,
+            poll_interval_ms: None,
+        })
+        .unwrap();
+        let path = log.to_string_lossy().into_owned();
+        let mut readers = BTreeMap::from([(path.clone(), files.open(&path).unwrap().unwrap())]);
+        let pending = Record {
+            topic: path.clone(),
+            ..Default::default()
+        };
+        let line = files.track(&pending);
+        files
+            .files()
+            .unwrap()
+            .get_mut(&path)
+            .unwrap()
+            .pending
+            .insert(0);
+        fs::remove_file(&log).unwrap();
+
+        // Kept while a line of the file is being processed.
+        files
+            .forget_vanished(&BTreeSet::new(), &mut readers)
+            .unwrap();
+        assert!(readers.contains_key(&path));
+        drop(line);
+        files
+            .forget_vanished(&BTreeSet::new(), &mut readers)
+            .unwrap();
+        assert!(readers.is_empty() && files.files().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_deleted() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (kept, deleted) = (dir.join("a.log"), dir.join("b.log"));
+        fs::write(&kept, "line\n").unwrap();
+        fs::write(&deleted, "line\n").unwrap();
+        let files = FileSource::new(&conf::Source::File {
+            paths: vec![dir.join("*.log").to_string_lossy().into_owned()],
+            follow: true,
+            state_file: None,
+            poll_interval_ms: None,
+        })
+        .unwrap();
+        let matching = files.matching().unwrap();
+        assert_eq!(matching.len(), 2);
+        // Deleted between the glob and the open, as logs are rotated.
+        fs::remove_file(&deleted).unwrap();
+        let mut readers = BTreeMap::new();
+        files.open_matching(&matching, &mut readers).unwrap();
+        let kept = kept.to_string_lossy().into_owned();
+        assert_eq!(readers.keys().collect::<Vec<_>>(), [&kept]);
+        assert_eq!(files.files().unwrap().keys().collect::<Vec<_>>(), [&kept]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
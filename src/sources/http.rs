@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context};
+use futures::Stream;
+use hyper::header::{HeaderMap, CONTENT_TYPE, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tracing::info;
+
+use super::PARTITION;
+use crate::component::Status;
+use crate::conf::{self, LineFormat};
+use crate::record::Record;
+use crate::sinks::Sinks;
+
+const TOPIC: &str = "http";
+const MAX_IN_FLIGHT: usize = 1024;
+/// Request header holding the key of a raw record.
+const KEY_HEADER: &str = "x-record-key";
+/// Prefix of the request headers that become headers of a raw record.
+const HEADER_PREFIX: &str = "x-record-header-";
+
+/// Accepts records posted to `/records` and answers each request once all
+/// of its records have been processed, with 200 OK when every one of them
+/// made it through the processors and sinks and 500 otherwise. S3 sinks
+/// upload what they buffered before a request is answered. Requests are
+/// rejected with 429 while too many records are in flight or while
+/// consumption is paused by backpressure.
+pub struct HttpSource {
+    listener: Mutex<Option<TcpListener>>,
+    ingest: Arc<Ingest>,
+}
+
+/// State shared with the requests being served.
+struct Ingest {
+    topic: String,
+    max_in_flight: usize,
+    in_flight: Arc<Semaphore>,
+    paused: AtomicBool,
+    next_offset: AtomicI64,
+    /// Where to report the outcome of each record, by offset.
+    replies: Mutex<HashMap<i64, oneshot::Sender<bool>>>,
+    /// Flushed before answering, so that S3 sinks upload the records of a
+    /// request as one.
+    sinks: Sinks,
+}
+
+/// Offsets of a request awaiting their outcome, forgotten when the request
+/// is answered or dropped.
+struct Pending<'a> {
+    replies: &'a Mutex<HashMap<i64, oneshot::Sender<bool>>>,
+    offsets: Vec<i64>,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut replies) = self.replies.lock() {
+            for offset in &self.offsets {
+                replies.remove(offset);
+            }
+        }
+    }
+}
+
+impl HttpSource {
+    /// Binds the endpoint, which is served once records are read.
+    pub fn new(source: &conf::Source, sinks: Sinks) -> anyhow::Result<Self> {
+        let conf::Source::Http {
+            listen,
+            topic,
+            max_in_flight,
+        } = source
+        else {
+            bail!("Not an HTTP source");
+        };
+        let max_in_flight = max_in_flight.unwrap_or(MAX_IN_FLIGHT);
+        if max_in_flight == 0 || u32::try_from(max_in_flight).is_err() {
+            bail!("max_in_flight is out of range");
+        }
+        let listener =
+            TcpListener::bind(listen).with_context(|| format!("Could not listen on {listen}"))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: Mutex::new(Some(listener)),
+            ingest: Arc::new(Ingest {
+                topic: topic.clone().unwrap_or_else(|| TOPIC.to_string()),
+                max_in_flight,
+                in_flight: Arc::new(Semaphore::new(max_in_flight)),
+                paused: AtomicBool::new(false),
+                // Offsets carry on from earlier runs, so that records are not
+                // taken for ones reflected in a restored checkpoint.
+                next_offset: AtomicI64::new(chrono::Utc::now().timestamp_micros()),
+                replies: Mutex::new(HashMap::new()),
+                sinks,
+            }),
+        })
+    }
+
+    /// Serves the endpoint and yields the records posted to it.
+    pub fn records(&self) -> impl Stream<Item = anyhow::Result<Record>> {
+        let (tx, rx) = mpsc::channel(self.ingest.max_in_flight);
+        let listener = self.listener.lock().ok().and_then(|mut l| l.take());
+        let ingest = self.ingest.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(listener, ingest, tx.clone()).await {
+                let _ = tx.send(Err(e)).await;
+            }
+        });
+        futures::stream::unfold(
+            rx,
+            |mut rx| async move { rx.recv().await.map(|rec| (rec, rx)) },
+        )
+    }
+
+    /// Where to report whether `rec` was processed. Dropping it reports a
+    /// failure.
+    pub fn reply(&self, rec: &Record) -> Option<oneshot::Sender<bool>> {
+        self.ingest.replies.lock().ok()?.remove(&rec.offset)
+    }
+
+    /// Starts or stops rejecting requests.
+    pub fn pause(&self, pause: bool) {
+        self.ingest.paused.store(pause, Ordering::Relaxed);
+    }
+}
+
+async fn serve(
+    listener: Option<TcpListener>,
+    ingest: Arc<Ingest>,
+    tx: mpsc::Sender<anyhow::Result<Record>>,
+) -> anyhow::Result<()> {
+    let listener = listener.ok_or_else(|| anyhow!("HTTP source is already served"))?;
+    let addr = listener.local_addr()?;
+    let make_svc = make_service_fn(move |_conn| {
+        let (ingest, tx) = (ingest.clone(), tx.clone());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let (ingest, tx) = (ingest.clone(), tx.clone());
+                async move { Ok::<_, Infallible>(ingest.handle(req, &tx).await) }
+            }))
+        }
+    });
+    let server = Server::from_tcp(listener)?.serve(make_svc);
+    info!(http_source_listen=%addr);
+    server.await?;
+    Ok(())
+}
+
+impl Ingest {
+    async fn handle(
+        &self,
+        req: Request<Body>,
+        tx: &mpsc::Sender<anyhow::Result<Record>>,
+    ) -> Response<Body> {
+        match (req.method(), req.uri().path()) {
+            (&Method::POST, "/records") => {}
+            (_, "/records") => return response(StatusCode::METHOD_NOT_ALLOWED, "use POST"),
+            _ => return response(StatusCode::NOT_FOUND, "not found"),
+        }
+        let records = match read_records(req).await {
+            Ok(records) => records,
+            Err(e) => return response(StatusCode::BAD_REQUEST, format!("{e:#}")),
+        };
+        let count = records.len();
+        if count > self.max_in_flight {
+            let reason = format!("at most {} records per request", self.max_in_flight);
+            return response(StatusCode::PAYLOAD_TOO_LARGE, reason);
+        }
+        if self.paused.load(Ordering::Relaxed) {
+            return busy();
+        }
+        // Held until the records are processed.
+        let _permits = match self.in_flight.clone().try_acquire_many_owned(count as u32) {
+            Ok(permits) => permits,
+            Err(_) => return busy(),
+        };
+        let mut replies = Vec::with_capacity(count);
+        let mut pending = Pending {
+            replies: &self.replies,
+            offsets: Vec::with_capacity(count),
+        };
+        for rec in records {
+            let offset = self.next_offset.fetch_add(1, Ordering::Relaxed);
+            let (reply, replied) = oneshot::channel();
+            if let Ok(mut replies) = self.replies.lock() {
+                replies.insert(offset, reply);
+            }
+            pending.offsets.push(offset);
+            let rec = Record {
+                topic: self.topic.clone(),
+                partition: PARTITION,
+                offset,
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                ..rec
+            };
+            if tx.send(Ok(rec)).await.is_err() {
+                return response(StatusCode::SERVICE_UNAVAILABLE, "flow stopped");
+            }
+            replies.push(replied);
+        }
+        let mut failed = 0;
+        for replied in replies {
+            if !replied.await.unwrap_or(false) {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            let reason = format!("{failed} of {count} records failed");
+            return response(StatusCode::INTERNAL_SERVER_ERROR, reason);
+        }
+        // S3 sinks only buffer what is written, so it is uploaded before the
+        // records are acknowledged.
+        if self.sinks.flush(&[PARTITION]).await == Status::Error {
+            return response(StatusCode::INTERNAL_SERVER_ERROR, "could not flush sinks");
+        }
+        response(StatusCode::OK, format!("{count} records processed"))
+    }
+}
+
+/// Records of a request body, by content type: a JSON record or an array of
+/// them, NDJSON, or else the raw body as the value of a single record.
+async fn read_records(req: Request<Body>) -> anyhow::Result<Vec<Record>> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .with_context(|| "Could not read request body")?;
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    match content_type.split(';').next().unwrap_or_default().trim() {
+        "application/json" => match serde_json::from_slice(&body)? {
+            serde_json::Value::Array(items) => items.into_iter().map(Record::from_json).collect(),
+            json => Ok(vec![Record::from_json(json)?]),
+        },
+        "application/x-ndjson" => body
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .map(|line| Record::from_line(line.to_vec(), LineFormat::Json))
+            .collect(),
+        _ => Ok(vec![raw_record(&parts.headers, body.to_vec())]),
+    }
+}
+
+/// A record holding `body` as its value, with the key and headers given in
+/// request headers.
+fn raw_record(headers: &HeaderMap, body: Vec<u8>) -> Record {
+    Record {
+        key: headers.get(KEY_HEADER).map(|v| v.as_bytes().to_vec()),
+        value: Some(body),
+        headers: headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str().strip_prefix(HEADER_PREFIX)?;
+                Some((name.to_string(), value.as_bytes().to_vec()))
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn busy() -> Response<Body> {
+    let mut resp = response(StatusCode::TOO_MANY_REQUESTS, "too many records in flight");
+    resp.headers_mut()
+        .insert(RETRY_AFTER, hyper::header::HeaderValue::from_static("1"));
+    resp
+}
+
+fn response(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut resp = Response::new(body.into());
+    *resp.status_mut() = status;
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use hyper::Client;
+    #[tokio::test]
+    async fn test_ingest() {
+        let source = conf::Source::Http {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            topic: None,
+            max_in_flight: Some(2),
+        };
+        let http = HttpSource::new(&source, Sinks::default()).unwrap();
+        let addr = http.listener.lock().unwrap().as_ref().unwrap().local_addr();
+        let uri = format!("http://{}/records", addr.unwrap());
+        let mut records = Box::pin(http.records());
+        let post = |content_type: &str, body: &'static str| {
+            let req = Request::post(&uri)
+                .header(CONTENT_TYPE, content_type)
+                .header("x-record-key", "k")
+                .header("x-record-header-type", "order")
+                .body(Body::from(body))
+                .unwrap();
+            tokio::spawn(Client::new().request(req))
+        };
+
+        let batch = post(
+            "application/json",
+            r#"[{"key": "a", "value": "1"}, {"key": "b", "value": "2"}]"#,
+        );
+        let first = records.next().await.unwrap().unwrap();
+        let second = records.next().await.unwrap().unwrap();
+        assert_eq!(first.topic, "http");
+        assert_eq!(first.key.as_deref(), Some(&b"a"[..]));
+        assert_eq!(second.offset, first.offset + 1);
+        // Both permits are held until the batch is processed.
+        let rejected = post("text/plain", "3").await.unwrap().unwrap();
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+        http.reply(&first).unwrap().send(true).unwrap();
+        http.reply(&second).unwrap().send(false).unwrap();
+        let resp = batch.await.unwrap().unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let raw = post("text/plain", "3");
+        let rec = records.next().await.unwrap().unwrap();
+        assert_eq!(rec.key.as_deref(), Some(&b"k"[..]));
+        assert_eq!(rec.value.as_deref(), Some(&b"3"[..]));
+        assert_eq!(rec.headers, [("type".to_string(), b"order".to_vec())]);
+        http.reply(&rec).unwrap().send(true).unwrap();
+        assert_eq!(raw.await.unwrap().unwrap().status(), StatusCode::OK);
+
+        // Records of requests that cannot be handed to the flow are
+        // forgotten.
+        drop(records);
+        let stopped = post("text/plain", "4").await.unwrap().unwrap();
+        assert_eq!(stopped.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(http.ingest.replies.lock().unwrap().is_empty());
+    }
+}
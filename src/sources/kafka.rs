@@ -1,51 +1,670 @@
-use anyhow::Context;
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
 
+use anyhow::{bail, Context};
+
+use futures::{Stream, StreamExt};
 use rdkafka::{
-    consumer::{Consumer, StreamConsumer},
-    ClientConfig,
+    client::{NativeClient, OAuthToken},
+    consumer::{BaseConsumer, CommitMode, Consumer, ConsumerContext, Rebalance, StreamConsumer},
+    statistics::Statistics,
+    types::RDKafkaRespErr,
+    ClientConfig, ClientContext, Offset, TopicPartitionList,
 };
+use regex::Regex;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
 
+use super::oauth::{self, ClusterContext, TokenSource};
+use super::properties;
+use super::rebalance::{Gate, InFlightRecords, Tracked, DRAIN_TIMEOUT};
+use super::stats::ConsumerStats;
+use crate::component::Status;
 use crate::conf;
+use crate::record::Record;
+use crate::sinks::Sinks;
+use crate::state::StateStore;
+use crate::watermark::Watermarks;
 
-pub fn create_kafka_consumer(cfg: &conf::Source) -> anyhow::Result<StreamConsumer> {
-    match cfg {
-        conf::Source::Kafka {
-            brokers,
-            topic,
-            group_id,
-            batch_size,
-            sasl,
-        } => {
-            let consumer: StreamConsumer = init_client_config(brokers, group_id, *batch_size, sasl)
-                .create()
-                .with_context(|| "Failed to initialize Kafka StreamConsumer.")?;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const STATISTICS_INTERVAL_MS: &str = "5000";
+/// Records read ahead of processing.
+const READ_AHEAD: usize = 1024;
+
+pub type KafkaConsumer = StreamConsumer<FlowConsumerContext>;
+
+/// Consumer context that hands partitions over cleanly on rebalance. Before
+/// partitions are revoked, it waits for their records in flight, flushes
+/// their sink buffers, checkpoints their state and commits their offsets.
+/// When partitions are assigned, it restores their state from the latest
+/// checkpoint or the changelog. Both run inside the rebalance callback, so no
+/// records of those partitions are delivered until they complete. With
+/// cooperative-sticky assignment only the partitions that move are affected.
+pub struct FlowConsumerContext {
+    state_store: StateStore,
+    watermarks: Watermarks,
+    sinks: Sinks,
+    in_flight: InFlightRecords,
+    gate: Gate,
+    stats: ConsumerStats,
+    consumer: OnceLock<Weak<KafkaConsumer>>,
+    tokens: Option<TokenSource>,
+    /// `start_from` of the sources by topic.
+    start_from: BTreeMap<String, conf::StartFrom>,
+}
+
+impl FlowConsumerContext {
+    pub fn new(state_store: StateStore, watermarks: Watermarks, sinks: Sinks) -> Self {
+        Self {
+            state_store,
+            watermarks,
+            sinks,
+            in_flight: InFlightRecords::default(),
+            gate: Gate::default(),
+            stats: ConsumerStats::default(),
+            consumer: OnceLock::new(),
+            tokens: None,
+            start_from: BTreeMap::new(),
+        }
+    }
+
+    /// Records being processed, which must be tracked for revoked partitions
+    /// to be drained.
+    pub fn in_flight(&self) -> &InFlightRecords {
+        &self.in_flight
+    }
+
+    /// Gate that records and checkpoints of the flow hold, which hand overs
+    /// take so as not to run alongside checkpoints.
+    pub fn gate(&self) -> &Gate {
+        &self.gate
+    }
+
+    /// Latest librdkafka statistics of the consumer.
+    pub fn stats(&self) -> &ConsumerStats {
+        &self.stats
+    }
+
+    /// Starts the partitions of `tpl` that the group has no committed offset
+    /// for where `start_from` says.
+    fn apply_start_from(&self, tpl: &mut TopicPartitionList) -> anyhow::Result<()> {
+        if self.start_from.is_empty() {
+            return Ok(());
+        }
+        let consumer = match self.consumer.get().and_then(Weak::upgrade) {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        let committed = consumer
+            .committed_offsets(tpl.clone(), TIMEOUT)
+            .with_context(|| "Could not fetch committed offsets")?;
+        let mut uncommitted = TopicPartitionList::new();
+        for elem in committed.elements() {
+            if !matches!(elem.offset(), Offset::Offset(_)) {
+                uncommitted.add_partition(elem.topic(), elem.partition());
+            }
+        }
+        let start = start_offsets(consumer.as_ref(), &uncommitted, &self.start_from)?;
+        for elem in start.elements() {
+            tpl.set_partition_offset(elem.topic(), elem.partition(), elem.offset())?;
+        }
+        if start.count() > 0 {
+            info!(start_offsets=?start);
+        }
+        Ok(())
+    }
+
+    /// Called from the rebalance callback, which runs on the task polling
+    /// the consumer, so records and checkpoints must run on other tasks.
+    fn hand_over(&self, partitions: &[i32]) -> anyhow::Result<()> {
+        let consumer = self.consumer.get().and_then(Weak::upgrade);
+        let hand_over = async {
+            // Offsets whose output did not make it out of the sink buffers
+            // are left for the next owner to process again.
+            if self.sinks.flush(partitions).await == Status::Error {
+                bail!("Could not flush sinks of revoked partitions {partitions:?}");
+            }
+            let offsets = self.state_store.hand_over(partitions).await?;
+            let consumer = match consumer {
+                Some(c) => c,
+                None => return Ok(()),
+            };
+            let mut tpl = TopicPartitionList::new();
+            for (topic, partition, offset) in offsets {
+                tpl.add_partition_offset(&topic, partition, Offset::Offset(offset))?;
+            }
+            if tpl.count() > 0 {
+                consumer
+                    .commit(&tpl, CommitMode::Sync)
+                    .with_context(|| "Could not commit offsets of revoked partitions")?;
+                info!(revoke_committed=?tpl);
+            }
+            anyhow::Ok(())
+        };
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(self.in_flight.hand_over(
+                partitions,
+                &self.gate,
+                DRAIN_TIMEOUT,
+                hand_over,
+            ))
+        })
+    }
+}
+
+impl ClientContext for FlowConsumerContext {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(
+        &self,
+        _oauthbearer_config: Option<&str>,
+    ) -> Result<OAuthToken, Box<dyn std::error::Error>> {
+        oauth::generate_oauth_token(self.tokens.as_ref())
+    }
+
+    fn stats(&self, statistics: Statistics) {
+        self.stats.record(&statistics);
+    }
+}
+
+impl ConsumerContext for FlowConsumerContext {
+    /// Applies `start_from` to assigned partitions before assigning them as
+    /// the default implementation does.
+    fn rebalance(
+        &self,
+        native_client: &NativeClient,
+        err: RDKafkaRespErr,
+        tpl: &mut TopicPartitionList,
+    ) {
+        if err == RDKafkaRespErr::RD_KAFKA_RESP_ERR__ASSIGN_PARTITIONS {
+            if let Err(e) = self.apply_start_from(tpl) {
+                error!(start_from_error=?e);
+            }
+        }
+        DefaultRebalance(self).rebalance(native_client, err, tpl);
+    }
+
+    fn pre_rebalance(&self, rebalance: &Rebalance<'_>) {
+        if let Rebalance::Revoke(tpl) = rebalance {
+            if let Err(e) = self.hand_over(&partitions(tpl)) {
+                error!(revoke_error=?e);
+            }
+        }
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance<'_>) {
+        match rebalance {
+            Rebalance::Assign(tpl) => {
+                let partitions = partitions(tpl);
+                info!(assigned_partitions=?partitions);
+                if let Err(e) = self.state_store.restore(&partitions) {
+                    error!(state_restore_error=?e);
+                }
+            }
+            Rebalance::Revoke(tpl) => {
+                let partitions = partitions(tpl);
+                info!(revoked_partitions=?partitions);
+                if let Err(e) = self.state_store.release(&partitions) {
+                    error!(state_release_error=?e);
+                }
+                if let Err(e) = self.watermarks.remove(&partitions) {
+                    error!(watermark_error=?e);
+                }
+            }
+            Rebalance::Error(_) => {}
+        }
+    }
+}
+
+/// Rebalances as rdkafka does by default, with the callbacks of the flow's
+/// context.
+struct DefaultRebalance<'a>(&'a FlowConsumerContext);
+
+impl ClientContext for DefaultRebalance<'_> {}
+
+impl ConsumerContext for DefaultRebalance<'_> {
+    fn pre_rebalance(&self, rebalance: &Rebalance<'_>) {
+        self.0.pre_rebalance(rebalance);
+    }
+
+    fn post_rebalance(&self, rebalance: &Rebalance<'_>) {
+        self.0.post_rebalance(rebalance);
+    }
+}
+
+/// Partition numbers of `tpl`. Partitions with the same number share state
+/// across topics, so each is listed once.
+fn partitions(tpl: &TopicPartitionList) -> Vec<i32> {
+    let mut partitions: Vec<i32> = tpl.elements().iter().map(|e| e.partition()).collect();
+    partitions.sort_unstable();
+    partitions.dedup();
+    partitions
+}
+
+/// Creates a consumer subscribed to the topics of all `sources`, which must
+/// share a cluster and consumer group. With several topics, partitions are
+/// assigned by range so that a member gets the same partition numbers of each
+/// topic.
+pub fn create_kafka_consumer(
+    sources: &[conf::Source],
+    mut context: FlowConsumerContext,
+) -> anyhow::Result<Arc<KafkaConsumer>> {
+    let (first, rest) = match sources.split_first() {
+        Some(split) => split,
+        None => bail!("At least one source must be configured"),
+    };
+    let conf::Source::Kafka {
+        brokers,
+        group_id,
+        batch_size,
+        sasl,
+        tls,
+        properties,
+        start_from,
+        assignment,
+        ..
+    } = first
+    else {
+        bail!("Only Kafka sources can be read together");
+    };
+    for source in rest {
+        let conf::Source::Kafka {
+            brokers: b,
+            group_id: g,
+            ..
+        } = source
+        else {
+            bail!("Only Kafka sources can be read together");
+        };
+        if b != brokers || g != group_id {
+            bail!("All sources must use the same brokers and group_id");
+        }
+    }
+    let mut client_config = connection_config(brokers, sasl, tls.as_ref());
+    context.tokens = TokenSource::new(sasl);
+    context.start_from = start_from_by_topic(sources)?;
+    if let Some(conf::StartFrom::Earliest | conf::StartFrom::Latest) = start_from {
+        let reset = match start_from {
+            Some(conf::StartFrom::Earliest) => "earliest",
+            _ => "latest",
+        };
+        client_config.set("auto.offset.reset", reset);
+    }
+    if batch_size.is_some() {
+        warn!("batch_size is ignored, use properties such as fetch.max.bytes instead");
+    }
+    properties::validate(properties).with_context(|| "Invalid Kafka source properties")?;
+    init_client_config(&mut client_config, group_id, properties);
+    let topics: Vec<&str> = sources.iter().flat_map(|s| s.topics()).collect();
+    if topics.is_empty() {
+        bail!("Sources must name at least one topic");
+    }
+    // State is keyed by partition number, so a member must own the same
+    // partition numbers of every topic it reads. Other assignments are
+    // rejected as the configuration is read.
+    let several_topics = topics.len() > 1 || topics.iter().any(|t| t.starts_with('^'));
+    let strategy = match assignment {
+        Some(conf::Assignment::Range) => Some("range"),
+        Some(conf::Assignment::RoundRobin) => Some("roundrobin"),
+        Some(conf::Assignment::CooperativeSticky) => Some("cooperative-sticky"),
+        None if several_topics => Some("range"),
+        None => None,
+    };
+    if let Some(strategy) = strategy {
+        client_config.set("partition.assignment.strategy", strategy);
+    }
+    let consumer: Arc<KafkaConsumer> = Arc::new(
+        client_config
+            .create_with_context(context)
+            .with_context(|| "Failed to initialize Kafka StreamConsumer.")?,
+    );
+    let _ = consumer.context().consumer.set(Arc::downgrade(&consumer));
+    oauth::refresh_in_background(consumer.client())?;
+    if several_topics {
+        check_partition_counts(&consumer, &topics)?;
+    }
+    consumer
+        .subscribe(&topics)
+        .with_context(|| format!("StreamConsumer failed to subscribe to topics: {topics:?}"))?;
+    Ok(consumer)
+}
+
+/// Records of `consumer`, tracked as in flight from the moment they are
+/// received. The consumer is polled on a task of its own, as rebalances block
+/// that task until revoked partitions are handed over.
+pub fn records(
+    consumer: &Arc<KafkaConsumer>,
+) -> impl Stream<Item = anyhow::Result<(Record, Tracked)>> {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    let consumer = consumer.clone();
+    tokio::spawn(async move {
+        let in_flight = consumer.context().in_flight().clone();
+        let mut messages = consumer.stream();
+        while let Some(msg) = messages.next().await {
+            let rec = msg.map_err(anyhow::Error::from).map(|msg| {
+                let rec = Record::from_message(&msg);
+                let tracked = in_flight.track(rec.partition);
+                (rec, tracked)
+            });
+            if tx.send(rec).await.is_err() {
+                break;
+            }
+        }
+    });
+    futures::stream::unfold(
+        rx,
+        |mut rx| async move { rx.recv().await.map(|rec| (rec, rx)) },
+    )
+}
+
+/// Start positions given by `start_from` for the topics of `partitions`.
+/// Partitions of a timestamp with no records at or after it start at the
+/// end.
+fn start_offsets<C: ConsumerContext>(
+    consumer: &impl Consumer<C>,
+    partitions: &TopicPartitionList,
+    start_from: &BTreeMap<String, conf::StartFrom>,
+) -> anyhow::Result<TopicPartitionList> {
+    let mut start = TopicPartitionList::new();
+    let mut by_time = TopicPartitionList::new();
+    for elem in partitions.elements() {
+        let (topic, partition) = (elem.topic(), elem.partition());
+        let offset = match start_from.get(topic) {
+            Some(conf::StartFrom::Earliest) => Offset::Beginning,
+            Some(conf::StartFrom::Latest) => Offset::End,
+            Some(conf::StartFrom::Offsets(offsets)) => match offsets.get(&partition) {
+                Some(offset) => Offset::Offset(*offset),
+                None => continue,
+            },
+            Some(conf::StartFrom::Timestamp(ts)) => {
+                let ts = start_timestamp(ts)?;
+                by_time.add_partition_offset(topic, partition, Offset::Offset(ts))?;
+                continue;
+            }
+            None => continue,
+        };
+        start.add_partition_offset(topic, partition, offset)?;
+    }
+    if by_time.count() > 0 {
+        let found = consumer
+            .offsets_for_times(by_time, TIMEOUT)
+            .with_context(|| "Could not look up offsets by timestamp")?;
+        for elem in found.elements() {
+            let offset = match elem.offset() {
+                Offset::Offset(offset) => Offset::Offset(offset),
+                _ => Offset::End,
+            };
+            start.add_partition_offset(elem.topic(), elem.partition(), offset)?;
+        }
+    }
+    Ok(start)
+}
+
+fn start_timestamp(ts: &str) -> anyhow::Result<i64> {
+    Ok(chrono::DateTime::parse_from_rfc3339(ts)
+        .with_context(|| format!("Invalid start_from timestamp: {ts}"))?
+        .timestamp_millis())
+}
+
+/// `start_from` of the sources by topic. Patterns are rejected, as their
+/// topics are not known up front.
+fn start_from_by_topic(
+    sources: &[conf::Source],
+) -> anyhow::Result<BTreeMap<String, conf::StartFrom>> {
+    let mut by_topic = BTreeMap::new();
+    for source in sources {
+        let conf::Source::Kafka {
+            start_from: Some(start_from),
+            ..
+        } = source
+        else {
+            continue;
+        };
+        if let conf::StartFrom::Timestamp(ts) = start_from {
+            start_timestamp(ts)?;
+        }
+        for topic in source.topics() {
+            if topic.starts_with('^') {
+                bail!("start_from cannot be combined with topic pattern {topic}");
+            }
+            by_topic.insert(topic.to_string(), start_from.clone());
+        }
+    }
+    Ok(by_topic)
+}
+
+/// Commits the `start_from` position of every partition of the sources'
+/// topics for their consumer group, even where it has committed offsets.
+/// Returns the committed offsets. Brokers only accept the commit while the
+/// group has no active members, so the flow must be stopped.
+pub fn reset_offsets(sources: &[conf::Source]) -> anyhow::Result<TopicPartitionList> {
+    let start_from = start_from_by_topic(sources)?;
+    let mut reset = TopicPartitionList::new();
+    for source in sources {
+        let conf::Source::Kafka { group_id, .. } = source else {
+            continue;
+        };
+        let (mut client_config, context) = client_config(source)?;
+        let consumer: BaseConsumer<ClusterContext> = client_config
+            .set("group.id", group_id)
+            .set("enable.auto.commit", "false")
+            .create_with_context(context)
+            .with_context(|| "Failed to initialize Kafka consumer.")?;
+        oauth::refresh_in_background(consumer.client())?;
+        let mut partitions = TopicPartitionList::new();
+        for topic in source.topics() {
+            if !start_from.contains_key(topic) {
+                continue;
+            }
+            for partition in partition_ids(&consumer, topic)? {
+                partitions.add_partition(topic, partition);
+            }
+        }
+        let mut commit = TopicPartitionList::new();
+        for elem in start_offsets(&consumer, &partitions, &start_from)?.elements() {
+            let (topic, partition) = (elem.topic(), elem.partition());
+            let offset = match elem.offset() {
+                Offset::Offset(offset) => offset,
+                Offset::Beginning => consumer.fetch_watermarks(topic, partition, TIMEOUT)?.0,
+                _ => consumer.fetch_watermarks(topic, partition, TIMEOUT)?.1,
+            };
+            commit.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+            reset.add_partition_offset(topic, partition, Offset::Offset(offset))?;
+        }
+        if commit.count() > 0 {
             consumer
-                .subscribe(&[topic])
-                .with_context(|| format!("StreamConsumer failed to subscribe to topic: {topic}"))?;
-            Ok(consumer)
+                .commit(&commit, CommitMode::Sync)
+                .with_context(|| format!("Could not reset the offsets of group {group_id}"))?;
         }
     }
+    Ok(reset)
 }
 
-fn init_client_config(
+fn partition_ids<C: ConsumerContext>(
+    consumer: &impl Consumer<C>,
+    topic: &str,
+) -> anyhow::Result<Vec<i32>> {
+    let metadata = consumer
+        .fetch_metadata(Some(topic), TIMEOUT)
+        .with_context(|| format!("Could not fetch metadata of topic: {topic}"))?;
+    Ok(metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == topic)
+        .flat_map(|t| t.partitions().iter().map(|p| p.id()))
+        .collect())
+}
+
+/// Fails unless the topics named or matched by a pattern in `topics` all
+/// have the same number of partitions, without which range assignment would
+/// give the same partition number of two topics to different members. Topics
+/// created later are not checked.
+fn check_partition_counts(consumer: &KafkaConsumer, topics: &[&str]) -> anyhow::Result<()> {
+    let patterns = topics
+        .iter()
+        .filter(|t| t.starts_with('^'))
+        .map(|p| Regex::new(p).with_context(|| format!("Invalid topic pattern {p}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let metadata = consumer
+        .fetch_metadata(None, TIMEOUT)
+        .with_context(|| "Could not fetch topic metadata")?;
+    let counts: Vec<(&str, usize)> = metadata
+        .topics()
+        .iter()
+        .filter(|t| topics.contains(&t.name()) || patterns.iter().any(|p| p.is_match(t.name())))
+        .map(|t| (t.name(), t.partitions().len()))
+        .collect();
+    if counts.windows(2).any(|w| w[0].1 != w[1].1) {
+        bail!("Topics read together must have the same number of partitions: {counts:?}");
+    }
+    Ok(())
+}
+
+/// Fails unless all `topics` have the same number of partitions, which joins
+/// rely on to see both sides of a key in the same partition.
+pub fn check_co_partitioned(consumer: &KafkaConsumer, topics: &[&str]) -> anyhow::Result<()> {
+    let mut counts = Vec::new();
+    for topic in topics {
+        let count = partition_ids(consumer, topic)?.len();
+        if count == 0 {
+            bail!("Topic {topic} has no partitions");
+        }
+        counts.push((topic, count));
+    }
+    if counts.windows(2).any(|w| w[0].1 != w[1].1) {
+        bail!("Joined topics are not co-partitioned: {counts:?}");
+    }
+    Ok(())
+}
+
+/// Connection settings and context shared by every client talking to the
+/// source cluster.
+pub fn client_config(cfg: &conf::Source) -> anyhow::Result<(ClientConfig, ClusterContext)> {
+    match cfg {
+        conf::Source::Kafka {
+            brokers, sasl, tls, ..
+        } => Ok((
+            connection_config(brokers, sasl, tls.as_ref()),
+            ClusterContext::new(sasl),
+        )),
+        _ => bail!("Not a Kafka source"),
+    }
+}
+
+pub fn connection_config(
     brokers: &[String],
-    group_id: &str,
-    batch_size: i32,
     sasl: &conf::SaslConfig,
+    tls: Option<&conf::TlsConfig>,
 ) -> ClientConfig {
     let mut cfg = ClientConfig::new();
+    cfg.set("bootstrap.servers", brokers.join(","));
+
+    let mechanism = match sasl {
+        conf::SaslConfig::None => None,
+        conf::SaslConfig::Plain { username, password } => {
+            cfg.set("sasl.username", username)
+                .set("sasl.password", password);
+            Some("PLAIN")
+        }
+        conf::SaslConfig::ScramSha256 { username, password } => {
+            cfg.set("sasl.username", username)
+                .set("sasl.password", password);
+            Some("SCRAM-SHA-256")
+        }
+        conf::SaslConfig::ScramSha512 { username, password } => {
+            cfg.set("sasl.username", username)
+                .set("sasl.password", password);
+            Some("SCRAM-SHA-512")
+        }
+        conf::SaslConfig::OAuthBearer(_) => {
+            // Tokens come from the client's context, refreshed on a queue
+            // of their own, see `oauth::refresh_in_background`.
+            cfg.set("enable_sasl_queue", "true");
+            Some("OAUTHBEARER")
+        }
+    };
+    match mechanism {
+        Some(mechanism) => {
+            cfg.set("security.protocol", "sasl_ssl")
+                .set("sasl.mechanisms", mechanism);
+        }
+        None if tls.is_some() => {
+            cfg.set("security.protocol", "ssl");
+        }
+        None => {}
+    }
+    if let Some(tls) = tls {
+        let paths = [
+            ("ssl.ca.location", &tls.ca_location),
+            ("ssl.certificate.location", &tls.certificate_location),
+            ("ssl.key.location", &tls.key_location),
+        ];
+        for (key, path) in paths {
+            if let Some(path) = path {
+                cfg.set(key, path.to_string_lossy());
+            }
+        }
+        if let Some(password) = &tls.key_password {
+            cfg.set("ssl.key.password", password);
+        }
+    }
+    cfg
+}
+
+/// Consumer defaults, overridden by `properties`. Offsets are committed by
+/// the flow once their records are processed rather than automatically.
+fn init_client_config(
+    cfg: &mut ClientConfig,
+    group_id: &str,
+    properties: &BTreeMap<String, String>,
+) {
     cfg.set("group.id", group_id)
-        .set("bootstrap.servers", brokers.join(","))
-        .set("batch.size", batch_size.to_string())
         .set("enable.partition.eof", "false")
-        .set("session.timeout.ms", "6000")
-        .set("enable.auto.commit", "false");
+        .set("enable.auto.commit", "false")
+        // Published as consumer lag and other metrics, and may be changed
+        // or disabled with "0" through the properties.
+        .set("statistics.interval.ms", STATISTICS_INTERVAL_MS);
+    for (key, value) in properties {
+        cfg.set(key, value);
+    }
+}
 
-    if let conf::SaslConfig::Plain { username, password } = sasl {
-        cfg.set("security.protocol", "sasl_ssl")
-            .set("sasl.mechanisms", "PLAIN")
-            .set("sasl.username", username)
-            .set("sasl.password", password);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_connection_config() {
+        let sasl = conf::SaslConfig::ScramSha512 {
+            username: "flow".to_string(),
+            password: "secret".to_string(),
+        };
+        let tls = conf::TlsConfig {
+            ca_location: Some("/etc/kafka/ca.pem".into()),
+            certificate_location: Some("/etc/kafka/client.pem".into()),
+            key_location: Some("/etc/kafka/client.key".into()),
+            key_password: Some("key-secret".to_string()),
+        };
+        let cfg = connection_config(&["broker:9093".to_string()], &sasl, Some(&tls));
+        assert_eq!(cfg.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(cfg.get("sasl.mechanisms"), Some("SCRAM-SHA-512"));
+        assert_eq!(cfg.get("ssl.ca.location"), Some("/etc/kafka/ca.pem"));
+        assert_eq!(cfg.get("ssl.key.password"), Some("key-secret"));
+        let debug = format!("{sasl:?} {tls:?}");
+        assert!(!debug.contains("flow") && !debug.contains("secret"));
+
+        let oauth = conf::SaslConfig::OAuthBearer(conf::TokenProvider::Unsecured {
+            principal: "flow".to_string(),
+        });
+        let cfg = connection_config(&[], &oauth, None);
+        assert_eq!(cfg.get("sasl.mechanisms"), Some("OAUTHBEARER"));
+        assert_eq!(cfg.get("enable_sasl_queue"), Some("true"));
+
+        let cfg = connection_config(&[], &conf::SaslConfig::None, Some(&tls));
+        assert_eq!(cfg.get("security.protocol"), Some("ssl"));
+        assert_eq!(
+            cfg.get("ssl.certificate.location"),
+            Some("/etc/kafka/client.pem")
+        );
     }
-    cfg
 }
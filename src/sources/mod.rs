@@ -1 +1,50 @@
+pub mod file;
+pub mod http;
 pub mod kafka;
+pub mod oauth;
+pub mod properties;
+pub mod rebalance;
+pub mod stats;
+pub mod stdin;
+
+use std::sync::Arc;
+
+use self::file::FileSource;
+use self::http::HttpSource;
+use self::kafka::KafkaConsumer;
+use self::stdin::StdinSource;
+
+/// Partition of the records of sources other than Kafka, which all share
+/// state.
+pub const PARTITION: i32 = 0;
+
+/// Where a flow reads its records from.
+pub enum Input {
+    Kafka(Arc<KafkaConsumer>),
+    File(FileSource),
+    Stdin(StdinSource),
+    Http(HttpSource),
+}
+
+impl Input {
+    pub fn kafka(&self) -> Option<&Arc<KafkaConsumer>> {
+        match self {
+            Input::Kafka(consumer) => Some(consumer),
+            _ => None,
+        }
+    }
+
+    pub fn file(&self) -> Option<&FileSource> {
+        match self {
+            Input::File(file) => Some(file),
+            _ => None,
+        }
+    }
+
+    pub fn http(&self) -> Option<&HttpSource> {
+        match self {
+            Input::Http(http) => Some(http),
+            _ => None,
+        }
+    }
+}
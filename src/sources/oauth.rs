@@ -0,0 +1,318 @@
+//! OAUTHBEARER tokens for the clients of a cluster. librdkafka asks for a
+//! token through `ClientContext::generate_oauth_token` whenever the current
+//! one is about to expire. It does so on its background thread, so fetching a
+//! token may block.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context};
+use base64::Engine;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Client, Request};
+use hyper_rustls::HttpsConnectorBuilder;
+use rdkafka::client::{Client as KafkaClient, OAuthToken};
+use rdkafka::consumer::ConsumerContext;
+use rdkafka::types::RDKafkaRespErr;
+use rdkafka::ClientContext;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::conf;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Lifetime of unsecured tokens, as librdkafka gives them.
+const UNSECURED_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Fetches tokens for the clients of a cluster using OAUTHBEARER.
+#[derive(Clone, Debug)]
+pub struct TokenSource {
+    provider: conf::TokenProvider,
+}
+
+/// Token response of an OAuth 2 token endpoint.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// Claims of a JWT access token the principal and expiry default to.
+#[derive(Default, Deserialize)]
+struct Claims {
+    #[serde(default)]
+    sub: Option<String>,
+    #[serde(default)]
+    exp: Option<i64>,
+}
+
+impl TokenSource {
+    /// Returns `None` unless `sasl` uses OAUTHBEARER.
+    pub fn new(sasl: &conf::SaslConfig) -> Option<Self> {
+        match sasl {
+            conf::SaslConfig::OAuthBearer(provider) => Some(Self {
+                provider: provider.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn token(&self) -> anyhow::Result<OAuthToken> {
+        match &self.provider {
+            conf::TokenProvider::Oidc {
+                token_endpoint_url,
+                client_id,
+                client_secret,
+                scope,
+            } => {
+                let resp = fetch_client_credentials(
+                    token_endpoint_url,
+                    client_id,
+                    client_secret,
+                    scope.as_deref(),
+                )
+                .with_context(|| format!("Could not fetch a token from {token_endpoint_url}"))?;
+                oauth_token(resp, None)
+            }
+            conf::TokenProvider::Command { command, principal } => {
+                let resp = run_command(command)
+                    .with_context(|| format!("Token command {command:?} failed"))?;
+                oauth_token(resp, principal.as_deref())
+            }
+            conf::TokenProvider::Unsecured { principal } => Ok(unsecured_token(principal)),
+        }
+    }
+}
+
+/// Context of the clients of a cluster other than the flow's consumer,
+/// which only need tokens.
+#[derive(Clone, Debug, Default)]
+pub struct ClusterContext {
+    tokens: Option<TokenSource>,
+}
+
+impl ClusterContext {
+    pub fn new(sasl: &conf::SaslConfig) -> Self {
+        Self {
+            tokens: TokenSource::new(sasl),
+        }
+    }
+}
+
+impl ClientContext for ClusterContext {
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn generate_oauth_token(
+        &self,
+        _oauthbearer_config: Option<&str>,
+    ) -> Result<OAuthToken, Box<dyn std::error::Error>> {
+        generate_oauth_token(self.tokens.as_ref())
+    }
+}
+
+impl ConsumerContext for ClusterContext {}
+
+/// Fetches a token for `generate_oauth_token`, which librdkafka only calls
+/// for clients configured with OAUTHBEARER.
+pub fn generate_oauth_token(
+    tokens: Option<&TokenSource>,
+) -> Result<OAuthToken, Box<dyn std::error::Error>> {
+    let tokens = tokens.ok_or("No OAUTHBEARER token provider is configured")?;
+    tokens.token().map_err(|e| {
+        error!(oauth_token_error=?e);
+        format!("{e:#}").into()
+    })
+}
+
+/// Has `client` refresh its token on librdkafka's background thread, so that
+/// it can connect before it is first polled. Clients that do not use
+/// OAUTHBEARER are left as they are.
+pub fn refresh_in_background<C: ClientContext>(client: &KafkaClient<C>) -> anyhow::Result<()> {
+    // SAFETY: the pointer is valid for the lifetime of `client`, and the
+    // returned error is owned by the caller and destroyed below.
+    unsafe {
+        let err = rdkafka_sys::rd_kafka_sasl_background_callbacks_enable(client.native_ptr());
+        if err.is_null() {
+            return Ok(());
+        }
+        let code = rdkafka_sys::rd_kafka_error_code(err);
+        let msg = std::ffi::CStr::from_ptr(rdkafka_sys::rd_kafka_error_string(err))
+            .to_string_lossy()
+            .into_owned();
+        rdkafka_sys::rd_kafka_error_destroy(err);
+        if code == RDKafkaRespErr::RD_KAFKA_RESP_ERR__NOT_CONFIGURED {
+            return Ok(());
+        }
+        Err(anyhow!(msg)).with_context(|| "Could not refresh OAUTHBEARER tokens in background")
+    }
+}
+
+/// Client credentials grant against an OpenID Connect token endpoint.
+fn fetch_client_credentials(
+    url: &str,
+    client_id: &str,
+    client_secret: &str,
+    scope: Option<&str>,
+) -> anyhow::Result<TokenResponse> {
+    let credentials =
+        base64::engine::general_purpose::STANDARD.encode(format!("{client_id}:{client_secret}"));
+    let mut body = "grant_type=client_credentials".to_string();
+    if let Some(scope) = scope {
+        body.push_str("&scope=");
+        body.push_str(&form_encode(scope));
+    }
+    let req = Request::post(url)
+        .header(AUTHORIZATION, format!("Basic {credentials}"))
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(body))?;
+    // This runs on librdkafka's background thread, outside of the runtime.
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let connector = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        let resp = tokio::time::timeout(TIMEOUT, Client::builder().build(connector).request(req))
+            .await
+            .with_context(|| "Token request timed out")??;
+        let status = resp.status();
+        let body = hyper::body::to_bytes(resp.into_body()).await?;
+        if !status.is_success() {
+            bail!(
+                "Token endpoint returned {status}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+        serde_json::from_slice(&body).with_context(|| "Invalid token response")
+    })
+}
+
+fn run_command(command: &[String]) -> anyhow::Result<TokenResponse> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("The token command is empty"))?;
+    let output = std::process::Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "Exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    serde_json::from_slice(&output.stdout).with_context(|| "Invalid token response")
+}
+
+/// Token for librdkafka. Unless configured, the principal is the `sub` claim
+/// of the token. The expiry is taken from the response, or else from the
+/// `exp` claim.
+fn oauth_token(resp: TokenResponse, principal: Option<&str>) -> anyhow::Result<OAuthToken> {
+    let claims = jwt_claims(&resp.access_token).unwrap_or_default();
+    let principal_name = principal
+        .map(str::to_string)
+        .or(claims.sub)
+        .ok_or_else(|| anyhow!("The token has no sub claim and no principal is configured"))?;
+    let lifetime_ms = match (resp.expires_in, claims.exp) {
+        (Some(expires_in), _) => now_ms() + expires_in as i64 * 1000,
+        (None, Some(exp)) => exp * 1000,
+        (None, None) => bail!("The token response has no expires_in and no exp claim"),
+    };
+    Ok(OAuthToken {
+        token: resp.access_token,
+        principal_name,
+        lifetime_ms,
+    })
+}
+
+fn jwt_claims(token: &str) -> Option<Claims> {
+    let payload = token.split('.').nth(1)?;
+    let json = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Unsigned JWT for `principal`, as librdkafka creates with
+/// `enable.sasl.oauthbearer.unsecure.jwt`.
+fn unsecured_token(principal: &str) -> OAuthToken {
+    let encode = |json: serde_json::Value| {
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json.to_string())
+    };
+    let now = now_ms();
+    let lifetime_ms = now + UNSECURED_LIFETIME.as_millis() as i64;
+    let header = encode(serde_json::json!({"alg": "none"}));
+    let claims = encode(serde_json::json!({
+        "sub": principal,
+        "iat": now / 1000,
+        "exp": lifetime_ms / 1000,
+    }));
+    OAuthToken {
+        token: format!("{header}.{claims}."),
+        principal_name: principal.to_string(),
+        lifetime_ms,
+    }
+}
+
+/// Percent-encodes `s` for a form body.
+fn form_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_token() {
+        let unsecured = TokenSource::new(&conf::SaslConfig::OAuthBearer(
+            conf::TokenProvider::Unsecured {
+                principal: "flow".to_string(),
+            },
+        ))
+        .unwrap()
+        .token()
+        .unwrap();
+        assert_eq!(unsecured.principal_name, "flow");
+        let claims = jwt_claims(&unsecured.token).unwrap();
+        assert_eq!(claims.sub.as_deref(), Some("flow"));
+        assert_eq!(claims.exp, Some(unsecured.lifetime_ms / 1000));
+
+        // The principal is taken from the token unless configured.
+        let response = format!(
+            r#"{{"access_token":"{}","expires_in":60}}"#,
+            unsecured.token
+        );
+        let command = |principal: Option<&str>| {
+            TokenSource::new(&conf::SaslConfig::OAuthBearer(
+                conf::TokenProvider::Command {
+                    command: vec!["echo".to_string(), response.clone()],
+                    principal: principal.map(str::to_string),
+                },
+            ))
+            .unwrap()
+            .token()
+        };
+        let token = command(None).unwrap();
+        assert_eq!(token.token, unsecured.token);
+        assert_eq!(token.principal_name, "flow");
+        assert!(token.lifetime_ms <= now_ms() + 60_000);
+        assert_eq!(command(Some("other")).unwrap().principal_name, "other");
+
+        assert!(TokenSource::new(&conf::SaslConfig::None).is_none());
+        assert_eq!(form_encode("read write"), "read%20write");
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{Notify, RwLock};
+use tracing::warn;
+
+/// How long a hand over waits for records of the revoked partitions before
+/// going on with what has been processed so far.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Held for reading while records are processed and for writing by
+/// checkpoints and hand overs, which then see no half-processed records.
+pub type Gate = Arc<RwLock<()>>;
+
+/// Counts the records being processed by partition, so that partitions can
+/// be drained before they are revoked.
+#[derive(Clone, Debug, Default)]
+pub struct InFlightRecords {
+    counts: Arc<Mutex<BTreeMap<i32, usize>>>,
+    drained: Arc<Notify>,
+}
+
+/// A record being processed, counted until dropped.
+pub struct Tracked {
+    records: InFlightRecords,
+    partition: i32,
+}
+
+impl InFlightRecords {
+    pub fn track(&self, partition: i32) -> Tracked {
+        if let Ok(mut counts) = self.counts.lock() {
+            *counts.entry(partition).or_default() += 1;
+        }
+        Tracked {
+            records: self.clone(),
+            partition,
+        }
+    }
+
+    /// Records of `partitions` being processed.
+    pub fn count(&self, partitions: &[i32]) -> usize {
+        self.counts.lock().map_or(0, |counts| {
+            partitions.iter().filter_map(|p| counts.get(p)).sum()
+        })
+    }
+
+    /// Waits until no records of `partitions` are being processed.
+    pub async fn drain(&self, partitions: &[i32]) {
+        loop {
+            let drained = self.drained.notified();
+            if self.count(partitions) == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+
+    /// Waits for the records of `partitions` to drain, then runs `hand_over`
+    /// with `gate` held, so that no checkpoint runs alongside it. Records
+    /// wait on the gate, so checkpoints must not run on the task waiting
+    /// here.
+    pub async fn hand_over<F: Future>(
+        &self,
+        partitions: &[i32],
+        gate: &RwLock<()>,
+        drain_timeout: Duration,
+        hand_over: F,
+    ) -> F::Output {
+        if tokio::time::timeout(drain_timeout, self.drain(partitions))
+            .await
+            .is_err()
+        {
+            warn!(
+                drain_timeout = ?partitions,
+                in_flight = self.count(partitions)
+            );
+        }
+        let _paused = gate.write().await;
+        hand_over.await
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Ok(mut counts) = self.records.counts.lock() {
+            if let Some(count) = counts.get_mut(&self.partition) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&self.partition);
+                    self.records.drained.notify_waiters();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    #[tokio::test]
+    async fn test_drain() {
+        let records = InFlightRecords::default();
+        let first = records.track(0);
+        let second = records.track(0);
+        let other = records.track(1);
+        assert_eq!(records.count(&[0, 1]), 3);
+        let drain = records.drain(&[0]);
+        tokio::pin!(drain);
+        drop(first);
+        assert!(tokio::time::timeout(Duration::from_millis(10), &mut drain)
+            .await
+            .is_err());
+        drop(second);
+        tokio::time::timeout(Duration::from_millis(10), drain)
+            .await
+            .unwrap();
+        // Records of other partitions are left running.
+        assert_eq!(records.count(&[0, 1]), 1);
+        drop(other);
+    }
+
+    #[tokio::test]
+    async fn test_hand_over_during_checkpoint() {
+        let records = InFlightRecords::default();
+        let gate = Gate::default();
+        let checkpoint = gate.clone().write_owned().await;
+        // A record of a revoked partition, waiting for the checkpoint.
+        let tracked = records.track(0);
+        let record = tokio::spawn({
+            let gate = gate.clone();
+            async move {
+                let _processing = gate.read().await;
+                drop(tracked);
+            }
+        });
+        let hand_over = tokio::spawn({
+            let (records, gate) = (records.clone(), gate.clone());
+            async move {
+                records
+                    .hand_over(&[0], &gate, Duration::from_secs(5), async {
+                        records.count(&[0])
+                    })
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!hand_over.is_finished());
+        drop(checkpoint);
+        // The record is processed before the hand over, well ahead of the
+        // drain timeout.
+        let in_flight = tokio::time::timeout(Duration::from_secs(1), hand_over)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(in_flight, 0);
+        record.await.unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex};
+
+use rdkafka::statistics::Statistics;
+
+/// Latest librdkafka statistics of the consumer that are published as
+/// metrics, replaced each `statistics.interval.ms`.
+#[derive(Clone, Debug, Default)]
+pub struct ConsumerStats {
+    latest: Arc<Mutex<Snapshot>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub partitions: Vec<PartitionStats>,
+    /// Rebalances of the consumer group since the consumer started.
+    pub rebalances: u64,
+    /// Average round-trip time in microseconds by broker.
+    pub broker_rtt: Vec<(String, i64)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PartitionStats {
+    pub topic: String,
+    pub partition: i32,
+    /// Messages between the high watermark and the position of the consumer,
+    /// `None` until both are known.
+    pub lag: Option<i64>,
+    pub fetch_queue_records: i64,
+    pub fetch_queue_bytes: u64,
+}
+
+impl ConsumerStats {
+    pub fn record(&self, statistics: &Statistics) {
+        let mut partitions: Vec<PartitionStats> = statistics
+            .topics
+            .values()
+            .flat_map(|t| {
+                t.partitions
+                    .values()
+                    // -1 is the internal partition of messages not yet
+                    // assigned to one, and only assigned partitions are
+                    // fetched.
+                    .filter(|p| p.partition >= 0 && p.desired)
+                    .map(|p| PartitionStats {
+                        topic: t.topic.clone(),
+                        partition: p.partition,
+                        lag: (p.consumer_lag >= 0).then_some(p.consumer_lag),
+                        fetch_queue_records: p.fetchq_cnt,
+                        fetch_queue_bytes: p.fetchq_size,
+                    })
+            })
+            .collect();
+        partitions.sort_by(|a, b| (&a.topic, a.partition).cmp(&(&b.topic, b.partition)));
+        let broker_rtt = statistics
+            .brokers
+            .values()
+            // Bootstrap brokers are replaced by the ones learned from
+            // metadata, which have a node id.
+            .filter(|b| b.nodeid >= 0)
+            .filter_map(|b| b.rtt.as_ref().map(|rtt| (b.nodename.clone(), rtt.avg)))
+            .collect();
+        let rebalances = statistics
+            .cgrp
+            .as_ref()
+            .map_or(0, |g| g.rebalance_cnt.max(0) as u64);
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = Snapshot {
+                partitions,
+                rebalances,
+                broker_rtt,
+            };
+        }
+    }
+
+    pub fn latest(&self) -> Snapshot {
+        self.latest.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::statistics::{Broker, ConsumerGroup, Partition, Topic, Window};
+    #[test]
+    fn test_record() {
+        let mut statistics = Statistics::default();
+        let partition = |partition, desired, consumer_lag| Partition {
+            partition,
+            desired,
+            consumer_lag,
+            fetchq_cnt: 3,
+            fetchq_size: 300,
+            ..Default::default()
+        };
+        let topic = Topic {
+            topic: "orders".to_string(),
+            partitions: [
+                (-1, partition(-1, false, -1)),
+                (0, partition(0, true, 42)),
+                (1, partition(1, true, -1)),
+                (2, partition(2, false, 7)),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        statistics.topics.insert("orders".to_string(), topic);
+        for (name, nodeid) in [("bootstrap", -1), ("b1:9092/1", 1)] {
+            let broker = Broker {
+                nodeid,
+                nodename: name.split('/').next().unwrap_or_default().to_string(),
+                rtt: Some(Window {
+                    avg: 1500,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            };
+            statistics.brokers.insert(name.to_string(), broker);
+        }
+        statistics.cgrp = Some(ConsumerGroup {
+            rebalance_cnt: 2,
+            ..Default::default()
+        });
+        let stats = ConsumerStats::default();
+        stats.record(&statistics);
+        let latest = stats.latest();
+        assert_eq!(
+            latest
+                .partitions
+                .iter()
+                .map(|p| (p.partition, p.lag))
+                .collect::<Vec<_>>(),
+            [(0, Some(42)), (1, None)]
+        );
+        assert_eq!(latest.partitions[0].fetch_queue_bytes, 300);
+        assert_eq!(latest.broker_rtt, [("b1:9092".to_string(), 1500)]);
+        assert_eq!(latest.rebalances, 2);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::BufRead;
+
+use anyhow::Context;
+use futures::Stream;
+use tokio::sync::mpsc;
+
+use super::PARTITION;
+use crate::conf::{self, LineFormat};
+use crate::record::Record;
+
+pub const TOPIC: &str = "stdin";
+/// Lines read ahead of the flow before reading blocks.
+const READ_AHEAD: usize = 1024;
+
+/// Reads newline-delimited records from standard input until it is closed.
+#[derive(Clone, Debug)]
+pub struct StdinSource {
+    format: LineFormat,
+}
+
+impl StdinSource {
+    pub fn new(source: &conf::Source) -> anyhow::Result<Self> {
+        match source {
+            conf::Source::Stdin { format } => Ok(Self { format: *format }),
+            _ => anyhow::bail!("Not a stdin source"),
+        }
+    }
+
+    pub fn records(&self) -> impl Stream<Item = anyhow::Result<Record>> {
+        let (tx, rx) = mpsc::channel(READ_AHEAD);
+        let format = self.format;
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = read(std::io::stdin().lock(), format, &tx) {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        futures::stream::unfold(
+            rx,
+            |mut rx| async move { rx.recv().await.map(|rec| (rec, rx)) },
+        )
+    }
+}
+
+/// Sends the lines of `input` as records until it ends or the flow stops
+/// receiving them. Blank lines are skipped in JSON.
+fn read(
+    mut input: impl BufRead,
+    format: LineFormat,
+    tx: &mpsc::Sender<anyhow::Result<Record>>,
+) -> anyhow::Result<()> {
+    let mut offset = 0;
+    loop {
+        let mut line = Vec::new();
+        let read = input
+            .read_until(b'\n', &mut line)
+            .with_context(|| "Could not read stdin")?;
+        if read == 0 {
+            return Ok(());
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+        }
+        let start = offset;
+        offset += read as i64;
+        if format == LineFormat::Json && line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let rec = Record::from_line(line, format)
+            .with_context(|| format!("Invalid record at byte {start} of stdin"))?;
+        let rec = Record {
+            topic: TOPIC.to_string(),
+            partition: PARTITION,
+            offset: start,
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            ..rec
+        };
+        if tx.blocking_send(Ok(rec)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_read() {
+        let input = concat!(
+            r#"{"key": "k1", "value": "plain", "headers": {"type": "order"}}"#,
+            "\n\n",
+            r#"{"value": {"total": 3}}"#,
+            "\r\n",
+            r#"{"key": "k3"}"#,
+        );
+        let (tx, mut rx) = mpsc::channel(READ_AHEAD);
+        read(input.as_bytes(), LineFormat::Json, &tx).unwrap();
+        drop(tx);
+        let mut records = Vec::new();
+        while let Some(rec) = rx.blocking_recv() {
+            records.push(rec.unwrap());
+        }
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].key.as_deref(), Some(&b"k1"[..]));
+        assert_eq!(
+            records[0].headers,
+            [("type".to_string(), b"order".to_vec())]
+        );
+        assert_eq!(records[1].value.as_deref(), Some(&br#"{"total":3}"#[..]));
+        assert_eq!(records[1].offset, 63);
+        assert!(records[2].value.is_none());
+        assert_eq!(
+            records[0].to_line(LineFormat::Json).unwrap(),
+            br#"{"key":"k1","value":"plain","headers":{"type":"order"}}"#
+        );
+        assert_eq!(
+            records[1].to_line(LineFormat::Json).unwrap(),
+            br#"{"value":{"total":3}}"#
+        );
+        // String values that look like JSON stay strings.
+        for line in [
+            r#"{"value":"123"}"#,
+            r#"{"value":"true"}"#,
+            r#"{"value":"{\"a\":1}"}"#,
+            r#"{"value":"\"text\""}"#,
+        ] {
+            let rec = Record::from_line(line.as_bytes().to_vec(), LineFormat::Json).unwrap();
+            assert_eq!(rec.to_line(LineFormat::Json).unwrap(), line.as_bytes());
+        }
+
+        let (tx, _rx) = mpsc::channel(READ_AHEAD);
+        assert!(read(&b"not json\n"[..], LineFormat::Json, &tx).is_err());
+    }
+}
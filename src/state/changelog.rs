@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use rdkafka::{
+    consumer::{BaseConsumer, Consumer},
+    message::{Header, Headers, OwnedHeaders},
+    producer::{FutureProducer, FutureRecord, Producer},
+    Message, Offset, TopicPartitionList,
+};
+use tracing::info;
+
+use crate::conf;
+use crate::sources::kafka::client_config;
+use crate::sources::oauth::{self, ClusterContext};
+
+const PROCESSOR_HEADER: &str = "wasmflow-processor";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mirrors state writes to a compacted Kafka topic. Entries for a source
+/// partition are written to the same partition of the changelog, keyed by
+/// `processor/partition/key`, so the topic needs at least as many partitions
+/// as the source topic.
+#[derive(Clone)]
+pub struct Changelog {
+    topic: String,
+    producer: FutureProducer<ClusterContext>,
+    client_config: rdkafka::ClientConfig,
+    context: ClusterContext,
+}
+
+impl std::fmt::Debug for Changelog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Changelog")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl Changelog {
+    pub fn new(topic: &str, source: &conf::Source) -> anyhow::Result<Self> {
+        let (client_config, context) =
+            client_config(source).with_context(|| "The changelog needs a Kafka source")?;
+        let producer: FutureProducer<ClusterContext> = client_config
+            .clone()
+            .set("enable.idempotence", "true")
+            .create_with_context(context.clone())
+            .with_context(|| "Failed to initialize changelog producer.")?;
+        oauth::refresh_in_background(producer.client())?;
+        Ok(Self {
+            topic: topic.to_string(),
+            producer,
+            client_config,
+            context,
+        })
+    }
+
+    /// Writes `value` for `key`, or a tombstone when `value` is `None`.
+    pub async fn write(
+        &self,
+        processor: &str,
+        partition: i32,
+        key: &[u8],
+        value: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        let mut changelog_key = format!("{processor}/{partition}/").into_bytes();
+        changelog_key.extend_from_slice(key);
+        let headers = OwnedHeaders::new().insert(Header {
+            key: PROCESSOR_HEADER,
+            value: Some(processor),
+        });
+        let mut record = FutureRecord::to(&self.topic)
+            .partition(partition)
+            .key(&changelog_key)
+            .headers(headers);
+        if let Some(v) = value {
+            record = record.payload(v);
+        }
+        self.producer
+            .send(record, TIMEOUT)
+            .await
+            .map_err(|(e, _)| anyhow!(e))
+            .with_context(|| format!("Could not write to changelog {}", self.topic))?;
+        Ok(())
+    }
+
+    /// Replays changelog `partition` from the beginning, calling `apply` with
+    /// the processor, key and value (`None` for deletes) of every entry and
+    /// `progress` with the number of offsets read and the total to read.
+    pub fn restore(
+        &self,
+        partition: i32,
+        mut apply: impl FnMut(&str, &[u8], Option<&[u8]>) -> anyhow::Result<()>,
+        mut progress: impl FnMut(u64, u64),
+    ) -> anyhow::Result<u64> {
+        let consumer: BaseConsumer<ClusterContext> = self
+            .client_config
+            .clone()
+            .set("group.id", "wasmflow-changelog-restore")
+            .set("enable.auto.commit", "false")
+            .create_with_context(self.context.clone())
+            .with_context(|| "Failed to initialize changelog consumer.")?;
+        oauth::refresh_in_background(consumer.client())?;
+        let (low, high) = consumer
+            .fetch_watermarks(&self.topic, partition, TIMEOUT)
+            .with_context(|| format!("Could not fetch watermarks for {}", self.topic))?;
+        let total = (high - low).max(0) as u64;
+        progress(0, total);
+        if total == 0 {
+            return Ok(0);
+        }
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition_offset(&self.topic, partition, Offset::Beginning)?;
+        consumer.assign(&tpl)?;
+        let mut restored = 0;
+        loop {
+            let msg = match consumer.poll(TIMEOUT) {
+                Some(msg) => msg?,
+                None => return Err(anyhow!("Timed out restoring {}", self.topic)),
+            };
+            let processor = msg
+                .headers()
+                .and_then(|h| h.iter().find(|h| h.key == PROCESSOR_HEADER))
+                .and_then(|h| h.value)
+                .and_then(|v| std::str::from_utf8(v).ok());
+            if let (Some(processor), Some(key)) = (processor, msg.key()) {
+                let prefix = format!("{processor}/{partition}/");
+                if let Some(key) = key.strip_prefix(prefix.as_bytes()) {
+                    apply(processor, key, msg.payload())?;
+                }
+            }
+            restored += 1;
+            progress((msg.offset() - low + 1) as u64, total);
+            if msg.offset() >= high - 1 {
+                break;
+            }
+        }
+        info!(changelog=%self.topic, partition, restored);
+        Ok(restored)
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use aws_sdk_s3::{types::ByteStream, types::SdkError, Client};
+use tracing::{info, warn};
+
+use super::codec::{put_bytes, take, take_bytes, take_u64};
+use crate::conf;
+use crate::sinks::s3::create_client;
+
+const MAGIC: &[u8; 4] = b"WFS2";
+const LATEST: &str = "LATEST";
+
+/// Key-value pairs of a state tree.
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Snapshot of every state tree of a partition, consistent with each source
+/// topic having been processed up to (but not including) its offset.
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub partition: i32,
+    pub offsets: Vec<(String, i64)>,
+    /// Entries by processor name.
+    pub trees: Vec<(String, Entries)>,
+}
+
+/// Writes snapshots to a local directory or S3. Each partition has its own
+/// prefix holding the snapshots and a `LATEST` object naming the newest
+/// complete one, which is only updated once the snapshot itself is written.
+#[derive(Debug)]
+pub struct Checkpoints {
+    storage: Storage,
+    interval: Duration,
+    written: Mutex<BTreeMap<i32, Vec<(String, i64)>>>,
+}
+
+#[derive(Debug)]
+enum Storage {
+    Local(PathBuf),
+    S3 {
+        client: Client,
+        bucket: String,
+        key_prefix: String,
+    },
+}
+
+impl Checkpoints {
+    pub async fn new(cfg: &conf::CheckpointConfig) -> anyhow::Result<Self> {
+        let storage = match &cfg.target {
+            conf::CheckpointTarget::Local { path } => {
+                std::fs::create_dir_all(path)
+                    .with_context(|| format!("Could not create checkpoint directory: {path:?}"))?;
+                Storage::Local(path.clone())
+            }
+            conf::CheckpointTarget::S3 {
+                region,
+                bucket,
+                key_prefix,
+            } => Storage::S3 {
+                client: create_client(region).await,
+                bucket: bucket.to_string(),
+                key_prefix: key_prefix.to_string(),
+            },
+        };
+        Ok(Self {
+            storage,
+            interval: Duration::from_secs(cfg.interval_secs),
+            written: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// True if a snapshot of `partition` at `offsets` has already been
+    /// written.
+    pub fn is_current(&self, partition: i32, offsets: &[(String, i64)]) -> bool {
+        self.written
+            .lock()
+            .is_ok_and(|w| w.get(&partition).is_some_and(|o| o == offsets))
+    }
+
+    /// Writes `snapshot`, points `LATEST` at it and removes the snapshot it
+    /// replaces.
+    pub async fn write(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        let partition = snapshot.partition;
+        let name = format!("{partition}/{}.snapshot", uuid::Uuid::new_v4());
+        let latest = format!("{partition}/{LATEST}");
+        let previous = self.storage.get(&latest).await?;
+        self.storage.put(&name, snapshot.encode()).await?;
+        self.storage.put(&latest, name.clone().into_bytes()).await?;
+        info!(checkpoint=%name);
+        if let Some(previous) = previous {
+            let previous = String::from_utf8_lossy(&previous);
+            if previous != name {
+                if let Err(e) = self.storage.delete(&previous).await {
+                    warn!(checkpoint_cleanup_error=?e);
+                }
+            }
+        }
+        self.written
+            .lock()
+            .map_err(|_| anyhow!("Checkpoint lock poisoned"))?
+            .insert(partition, snapshot.offsets.clone());
+        Ok(())
+    }
+
+    /// Reads the latest complete snapshot of `partition`, if any.
+    pub async fn read_latest(&self, partition: i32) -> anyhow::Result<Option<Snapshot>> {
+        let name = match self.storage.get(&format!("{partition}/{LATEST}")).await? {
+            Some(name) => String::from_utf8(name)
+                .with_context(|| format!("Invalid {LATEST} checkpoint for {partition}"))?,
+            None => return Ok(None),
+        };
+        let body = self
+            .storage
+            .get(&name)
+            .await?
+            .ok_or_else(|| anyhow!("Checkpoint {name} is missing"))?;
+        let snapshot =
+            Snapshot::decode(&body).with_context(|| format!("Invalid checkpoint {name}"))?;
+        if snapshot.partition != partition {
+            bail!(
+                "Checkpoint {name} belongs to partition {}",
+                snapshot.partition
+            );
+        }
+        self.written
+            .lock()
+            .map_err(|_| anyhow!("Checkpoint lock poisoned"))?
+            .insert(partition, snapshot.offsets.clone());
+        Ok(Some(snapshot))
+    }
+}
+
+impl Storage {
+    async fn put(&self, name: &str, body: Vec<u8>) -> anyhow::Result<()> {
+        match self {
+            Storage::Local(dir) => {
+                let path = dir.join(name);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                let tmp = dir.join(format!("{}.tmp", uuid::Uuid::new_v4()));
+                std::fs::write(&tmp, body)
+                    .and_then(|_| std::fs::rename(&tmp, &path))
+                    .with_context(|| format!("Could not write checkpoint: {path:?}"))
+            }
+            Storage::S3 {
+                client,
+                bucket,
+                key_prefix,
+            } => {
+                client
+                    .put_object()
+                    .bucket(bucket.to_string())
+                    .key(format!("{key_prefix}/{name}"))
+                    .body(ByteStream::from(body))
+                    .send()
+                    .await
+                    .with_context(|| format!("Could not upload checkpoint: {key_prefix}/{name}"))?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn get(&self, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Storage::Local(dir) => {
+                let path = dir.join(name);
+                match std::fs::read(&path) {
+                    Ok(body) => Ok(Some(body)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => {
+                        Err(e).with_context(|| format!("Could not read checkpoint: {path:?}"))
+                    }
+                }
+            }
+            Storage::S3 {
+                client,
+                bucket,
+                key_prefix,
+            } => {
+                let key = format!("{key_prefix}/{name}");
+                match client
+                    .get_object()
+                    .bucket(bucket.to_string())
+                    .key(key.clone())
+                    .send()
+                    .await
+                {
+                    Ok(resp) => {
+                        let body = resp
+                            .body
+                            .collect()
+                            .await
+                            .with_context(|| format!("Could not download checkpoint: {key}"))?;
+                        Ok(Some(body.into_bytes().to_vec()))
+                    }
+                    Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => Ok(None),
+                    Err(e) => {
+                        Err(e).with_context(|| format!("Could not download checkpoint: {key}"))
+                    }
+                }
+            }
+        }
+    }
+
+    async fn delete(&self, name: &str) -> anyhow::Result<()> {
+        match self {
+            Storage::Local(dir) => std::fs::remove_file(dir.join(name))
+                .with_context(|| format!("Could not remove checkpoint: {name}")),
+            Storage::S3 {
+                client,
+                bucket,
+                key_prefix,
+            } => {
+                client
+                    .delete_object()
+                    .bucket(bucket.to_string())
+                    .key(format!("{key_prefix}/{name}"))
+                    .send()
+                    .await
+                    .with_context(|| format!("Could not remove checkpoint: {key_prefix}/{name}"))?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Length-prefixed big-endian encoding, starting with a format marker.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&self.partition.to_be_bytes());
+        buf.extend_from_slice(&(self.offsets.len() as u64).to_be_bytes());
+        for (topic, offset) in &self.offsets {
+            put_bytes(&mut buf, topic.as_bytes());
+            buf.extend_from_slice(&offset.to_be_bytes());
+        }
+        buf.extend_from_slice(&(self.trees.len() as u64).to_be_bytes());
+        for (name, entries) in &self.trees {
+            put_bytes(&mut buf, name.as_bytes());
+            buf.extend_from_slice(&(entries.len() as u64).to_be_bytes());
+            for (k, v) in entries {
+                put_bytes(&mut buf, k);
+                put_bytes(&mut buf, v);
+            }
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> anyhow::Result<Self> {
+        if take(&mut buf, MAGIC.len())? != MAGIC {
+            bail!("Unknown snapshot format");
+        }
+        let partition = i32::from_be_bytes(take(&mut buf, 4)?.try_into()?);
+        let mut offsets = Vec::new();
+        for _ in 0..take_u64(&mut buf)? {
+            let topic = String::from_utf8(take_bytes(&mut buf)?.to_vec())?;
+            offsets.push((topic, i64::from_be_bytes(take(&mut buf, 8)?.try_into()?)));
+        }
+        let mut trees = Vec::new();
+        for _ in 0..take_u64(&mut buf)? {
+            let name = String::from_utf8(take_bytes(&mut buf)?.to_vec())?;
+            let mut entries = Vec::new();
+            for _ in 0..take_u64(&mut buf)? {
+                let k = take_bytes(&mut buf)?.to_vec();
+                let v = take_bytes(&mut buf)?.to_vec();
+                entries.push((k, v));
+            }
+            trees.push((name, entries));
+        }
+        Ok(Self {
+            partition,
+            offsets,
+            trees,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn test_local_checkpoints() {
+        let dir = std::env::temp_dir().join(format!("wasmflow-{}", uuid::Uuid::new_v4()));
+        let cfg = conf::CheckpointConfig {
+            interval_secs: 1,
+            target: conf::CheckpointTarget::Local { path: dir.clone() },
+        };
+        let checkpoints = Checkpoints::new(&cfg).await.unwrap();
+        assert_eq!(checkpoints.read_latest(3).await.unwrap(), None);
+        let mut snapshot = Snapshot {
+            partition: 3,
+            offsets: vec![("orders".to_string(), 42)],
+            trees: vec![("counter".to_string(), vec![(b"k".to_vec(), b"v".to_vec())])],
+        };
+        checkpoints.write(&snapshot).await.unwrap();
+        snapshot.offsets.push(("customers".to_string(), 7));
+        checkpoints.write(&snapshot).await.unwrap();
+        assert!(checkpoints.is_current(3, &snapshot.offsets));
+        assert_eq!(checkpoints.read_latest(3).await.unwrap(), Some(snapshot));
+        assert_eq!(std::fs::read_dir(dir.join("3")).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+//! Byte encodings for state keys and values.
+
+use anyhow::bail;
+
+/// Big-endian with the sign bit flipped, so that byte order matches numeric
+/// order.
+pub fn encode_i64(v: i64) -> Vec<u8> {
+    ((v as u64) ^ (1 << 63)).to_be_bytes().to_vec()
+}
+
+pub fn decode_i64(bytes: &[u8]) -> anyhow::Result<i64> {
+    Ok((u64::from_be_bytes(bytes.try_into()?) ^ (1 << 63)) as i64)
+}
+
+/// Appends `bytes` prefixed with their length.
+pub fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u64).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Splits `len` bytes off the front of `buf`.
+pub fn take<'a>(buf: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    if buf.len() < len {
+        bail!("Encoded value is truncated");
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+pub fn take_u64(buf: &mut &[u8]) -> anyhow::Result<u64> {
+    Ok(u64::from_be_bytes(take(buf, 8)?.try_into()?))
+}
+
+/// Splits off bytes written by `put_bytes`.
+pub fn take_bytes<'a>(buf: &mut &'a [u8]) -> anyhow::Result<&'a [u8]> {
+    let len = take_u64(buf)?;
+    take(buf, usize::try_from(len)?)
+}
@@ -0,0 +1,433 @@
+pub mod changelog;
+pub mod checkpoint;
+pub mod codec;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use tracing::{error, info};
+
+use crate::component::{state_store, Status};
+use crate::conf;
+
+use self::changelog::Changelog;
+use self::checkpoint::{Checkpoints, Snapshot};
+pub use self::codec::{decode_i64, encode_i64};
+
+/// Embedded on-disk key-value store holding guest state. Each processor and
+/// Kafka partition gets its own keyspace so state can be handed over along
+/// with partition assignment.
+#[derive(Clone, Debug)]
+pub struct StateStore {
+    db: sled::Db,
+    persistent: bool,
+    changelog: Option<Changelog>,
+    checkpoints: Option<Arc<Checkpoints>>,
+    restoring: Arc<Mutex<BTreeMap<i32, RestoreProgress>>>,
+    positions: Arc<Mutex<BTreeMap<i32, Offsets>>>,
+}
+
+/// Next offset to process by source topic.
+type Offsets = BTreeMap<String, i64>;
+
+/// Changelog offsets replayed so far for a partition being restored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RestoreProgress {
+    pub restored: u64,
+    pub total: u64,
+}
+
+impl StateStore {
+    /// Opens the store at the configured path, or a temporary store that is
+    /// discarded on exit when no state config is given. Writes are mirrored
+    /// to `changelog` when present.
+    pub fn new(
+        cfg: Option<&conf::StateConfig>,
+        changelog: Option<Changelog>,
+        checkpoints: Option<Checkpoints>,
+    ) -> anyhow::Result<Self> {
+        let db = match cfg {
+            Some(cfg) => sled::open(&cfg.path)
+                .with_context(|| format!("Could not open state store: {:?}", cfg.path))?,
+            None => sled::Config::new()
+                .temporary(true)
+                .open()
+                .with_context(|| "Could not open temporary state store")?,
+        };
+        info!(state_store_recovered=%db.was_recovered());
+        Ok(Self {
+            db,
+            persistent: cfg.is_some(),
+            changelog,
+            checkpoints: checkpoints.map(Arc::new),
+            restoring: Arc::new(Mutex::new(BTreeMap::new())),
+            positions: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    /// False for the temporary store used without a state config.
+    pub fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    pub fn partition(&self, processor: &str, partition: i32) -> anyhow::Result<PartitionState> {
+        let tree = self
+            .db
+            .open_tree(tree_name(processor, partition))
+            .with_context(|| format!("Could not open state for {processor}/{partition}"))?;
+        Ok(PartitionState {
+            tree,
+            processor: processor.to_string(),
+            partition,
+            changelog: self.changelog.clone(),
+        })
+    }
+
+    /// Replaces the local state of `partitions` with their latest checkpoint,
+    /// or with the contents of the changelog when there is no checkpoint.
+    /// Local state is kept as is when neither is configured.
+    pub fn restore(&self, partitions: &[i32]) -> anyhow::Result<()> {
+        {
+            let mut positions = self.positions()?;
+            for &partition in partitions {
+                positions.insert(partition, Offsets::new());
+            }
+        }
+        if self.checkpoints.is_none() && self.changelog.is_none() {
+            return Ok(());
+        }
+        self.set_progress(partitions.iter().map(|p| (*p, RestoreProgress::default())))?;
+        for &partition in partitions {
+            for (_, tree) in self.partition_trees(partition)? {
+                tree.clear()?;
+            }
+            if !self.restore_checkpoint(partition)? {
+                self.restore_changelog(partition)?;
+            }
+            self.restoring
+                .lock()
+                .map_err(|_| anyhow!("Restore progress lock poisoned"))?
+                .remove(&partition);
+        }
+        Ok(())
+    }
+
+    /// Called from the rebalance callback, which runs on a runtime worker
+    /// while the consumer is polled, so the download blocks in place.
+    fn restore_checkpoint(&self, partition: i32) -> anyhow::Result<bool> {
+        let checkpoints = match &self.checkpoints {
+            Some(c) => c,
+            None => return Ok(false),
+        };
+        let snapshot = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(checkpoints.read_latest(partition))
+        })?;
+        let snapshot = match snapshot {
+            Some(s) => s,
+            None => return Ok(false),
+        };
+        for (processor, entries) in snapshot.trees {
+            let tree = self.db.open_tree(tree_name(&processor, partition))?;
+            for (k, v) in entries {
+                tree.insert(k, v)?;
+            }
+        }
+        info!(checkpoint_restored = partition, offsets = ?snapshot.offsets);
+        self.positions()?
+            .insert(partition, snapshot.offsets.into_iter().collect());
+        Ok(true)
+    }
+
+    fn restore_changelog(&self, partition: i32) -> anyhow::Result<()> {
+        let changelog = match &self.changelog {
+            Some(c) => c,
+            None => return Ok(()),
+        };
+        changelog.restore(
+            partition,
+            |processor, key, value| {
+                let tree = self.db.open_tree(tree_name(processor, partition))?;
+                match value {
+                    Some(v) => tree.insert(key, v)?,
+                    None => tree.remove(key)?,
+                };
+                Ok(())
+            },
+            |restored, total| {
+                if let Err(e) =
+                    self.set_progress([(partition, RestoreProgress { restored, total })])
+                {
+                    error!(state_restore_error=?e);
+                }
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Stops tracking offsets of partitions that are no longer assigned, so
+    /// they are left out of later checkpoints.
+    pub fn release(&self, partitions: &[i32]) -> anyhow::Result<()> {
+        let mut positions = self.positions()?;
+        for partition in partitions {
+            positions.remove(partition);
+        }
+        Ok(())
+    }
+
+    /// False for records already reflected in the restored checkpoint.
+    pub fn should_process(&self, topic: &str, partition: i32, offset: i64) -> bool {
+        self.positions().map_or(true, |p| {
+            p.get(&partition)
+                .and_then(|o| o.get(topic))
+                .is_none_or(|next| offset >= *next)
+        })
+    }
+
+    /// Records that the record at `offset` has been processed.
+    pub fn advance(&self, topic: &str, partition: i32, offset: i64) -> anyhow::Result<()> {
+        if let Some(offsets) = self.positions()?.get_mut(&partition) {
+            let next = offsets.entry(topic.to_string()).or_insert(offset + 1);
+            *next = (*next).max(offset + 1);
+        }
+        Ok(())
+    }
+
+    /// Partitions assigned to the flow, whose offsets checkpoints commit.
+    pub fn assigned(&self) -> anyhow::Result<Vec<i32>> {
+        Ok(self.positions()?.keys().copied().collect())
+    }
+
+    pub fn checkpoint_interval(&self) -> Option<Duration> {
+        self.checkpoints.as_ref().map(|c| c.interval())
+    }
+
+    /// Returns the offsets to commit for the processed records of every
+    /// assigned partition, first writing a snapshot of those that processed
+    /// records since their last checkpoint when checkpoints are configured.
+    /// Callers must make sure no records are processed until it returns.
+    pub async fn checkpoint(&self) -> anyhow::Result<Vec<(String, i32, i64)>> {
+        self.snapshot(None).await
+    }
+
+    /// Returns the offsets to commit for `partitions` before they are revoked,
+    /// checkpointing them first when checkpoints are configured so that the
+    /// next owner restores state matching those offsets. Callers must make
+    /// sure no records of `partitions` are being processed.
+    pub async fn hand_over(&self, partitions: &[i32]) -> anyhow::Result<Vec<(String, i32, i64)>> {
+        self.snapshot(Some(partitions)).await
+    }
+
+    async fn snapshot(&self, only: Option<&[i32]>) -> anyhow::Result<Vec<(String, i32, i64)>> {
+        let positions: Vec<(i32, Vec<(String, i64)>)> = self
+            .positions()?
+            .iter()
+            .filter(|(p, offsets)| !offsets.is_empty() && only.is_none_or(|o| o.contains(p)))
+            .map(|(p, offsets)| (*p, offsets.clone().into_iter().collect()))
+            .collect();
+        let mut commit = Vec::new();
+        for (partition, offsets) in positions {
+            commit.extend(offsets.iter().map(|(t, o)| (t.clone(), partition, *o)));
+            let checkpoints = match &self.checkpoints {
+                Some(c) if !c.is_current(partition, &offsets) => c,
+                _ => continue,
+            };
+            let trees = self
+                .partition_trees(partition)?
+                .into_iter()
+                .map(|(processor, tree)| {
+                    let entries = tree
+                        .iter()
+                        .map(|kv| {
+                            let (k, v) = kv?;
+                            Ok((k.to_vec(), v.to_vec()))
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    Ok((processor, entries))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            checkpoints
+                .write(&Snapshot {
+                    partition,
+                    offsets,
+                    trees,
+                })
+                .await?;
+        }
+        Ok(commit)
+    }
+
+    /// State trees of `partition` by processor name.
+    fn partition_trees(&self, partition: i32) -> anyhow::Result<Vec<(String, sled::Tree)>> {
+        let suffix = format!("/{partition}");
+        let mut trees = Vec::new();
+        for name in self.db.tree_names() {
+            let name = String::from_utf8_lossy(&name).into_owned();
+            if let Some(processor) = name.strip_suffix(&suffix) {
+                trees.push((processor.to_string(), self.db.open_tree(&name)?));
+            }
+        }
+        Ok(trees)
+    }
+
+    fn positions(&self) -> anyhow::Result<MutexGuard<'_, BTreeMap<i32, Offsets>>> {
+        self.positions
+            .lock()
+            .map_err(|_| anyhow!("Offset positions lock poisoned"))
+    }
+
+    fn set_progress(
+        &self,
+        progress: impl IntoIterator<Item = (i32, RestoreProgress)>,
+    ) -> anyhow::Result<()> {
+        self.restoring
+            .lock()
+            .map_err(|_| anyhow!("Restore progress lock poisoned"))?
+            .extend(progress);
+        Ok(())
+    }
+
+    /// Progress of the partitions currently being restored.
+    pub fn restore_progress(&self) -> Vec<(i32, RestoreProgress)> {
+        self.restoring
+            .lock()
+            .map(|r| r.iter().map(|(p, r)| (*p, *r)).collect())
+            .unwrap_or_default()
+    }
+
+    /// True once no partition is being restored.
+    pub fn is_ready(&self) -> bool {
+        self.restoring.lock().is_ok_and(|r| r.is_empty())
+    }
+}
+
+fn tree_name(processor: &str, partition: i32) -> String {
+    format!("{processor}/{partition}")
+}
+
+/// State visible to a single guest invocation, scoped to the processor and
+/// partition of the record being processed.
+#[derive(Clone, Debug)]
+pub struct PartitionState {
+    tree: sled::Tree,
+    processor: String,
+    partition: i32,
+    changelog: Option<Changelog>,
+}
+
+impl PartitionState {
+    pub fn get(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.tree.get(key)?.map(|v| v.to_vec()))
+    }
+
+    /// Writes go to the changelog first so that it never lags behind the
+    /// local store.
+    pub async fn put(&self, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        if let Some(changelog) = &self.changelog {
+            changelog
+                .write(&self.processor, self.partition, key, Some(value))
+                .await?;
+        }
+        self.tree.insert(key, value)?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, key: &[u8]) -> anyhow::Result<()> {
+        if let Some(changelog) = &self.changelog {
+            changelog
+                .write(&self.processor, self.partition, key, None)
+                .await?;
+        }
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    /// Removes `key` and returns its value. When called concurrently for the
+    /// same key, only one caller gets the value.
+    pub async fn take(&self, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        if let Some(changelog) = &self.changelog {
+            changelog
+                .write(&self.processor, self.partition, key, None)
+                .await?;
+        }
+        Ok(self.tree.remove(key)?.map(|v| v.to_vec()))
+    }
+
+    pub fn scan_prefix(&self, prefix: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.tree
+            .scan_prefix(prefix)
+            .map(|kv| {
+                let (k, v) = kv?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect()
+    }
+
+    /// Entries with keys ordered before `end`.
+    pub fn scan_until(&self, end: &[u8]) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.tree
+            .range(..end)
+            .map(|kv| {
+                let (k, v) = kv?;
+                Ok((k.to_vec(), v.to_vec()))
+            })
+            .collect()
+    }
+}
+
+impl state_store::Host for PartitionState {
+    async fn get(&mut self, key: Vec<u8>) -> Option<Vec<u8>> {
+        PartitionState::get(self, &key).unwrap_or_else(|e| {
+            error!(state_store_error=?e);
+            None
+        })
+    }
+
+    async fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Status {
+        status(PartitionState::put(self, &key, &value).await)
+    }
+
+    async fn delete(&mut self, key: Vec<u8>) -> Status {
+        status(PartitionState::delete(self, &key).await)
+    }
+
+    async fn scan_prefix(&mut self, prefix: Vec<u8>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        PartitionState::scan_prefix(self, &prefix).unwrap_or_else(|e| {
+            error!(state_store_error=?e);
+            Vec::new()
+        })
+    }
+}
+
+fn status(res: anyhow::Result<()>) -> Status {
+    match res {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            error!(state_store_error=?e);
+            Status::Error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[tokio::test]
+    async fn test_partition_scoping() {
+        let store = StateStore::new(None, None, None).unwrap();
+        let p0 = store.partition("counter", 0).unwrap();
+        let p1 = store.partition("counter", 1).unwrap();
+        p0.put(b"user/1", b"10").await.unwrap();
+        p0.put(b"user/2", b"20").await.unwrap();
+        p0.put(b"other", b"30").await.unwrap();
+        p1.put(b"user/1", b"11").await.unwrap();
+        assert_eq!(p0.get(b"user/1").unwrap(), Some(b"10".to_vec()));
+        assert_eq!(p1.get(b"user/1").unwrap(), Some(b"11".to_vec()));
+        assert_eq!(p0.scan_prefix(b"user/").unwrap().len(), 2);
+        p0.delete(b"user/1").await.unwrap();
+        assert_eq!(p0.get(b"user/1").unwrap(), None);
+        assert_eq!(p1.get(b"user/1").unwrap(), Some(b"11".to_vec()));
+        assert!(store.is_ready());
+    }
+}
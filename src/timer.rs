@@ -0,0 +1,79 @@
+use tracing::error;
+
+use crate::component::{timers, Status};
+use crate::state::{decode_i64, encode_i64, PartitionState};
+
+/// Name of the state tree holding the timers of `processor`, keyed by time
+/// and then by timer key.
+pub fn tree_name(processor: &str) -> String {
+    format!("{processor}#timers")
+}
+
+/// Timers of the processor and partition of the record being processed.
+#[derive(Clone, Debug)]
+pub struct TimerScheduler {
+    pub state: PartitionState,
+}
+
+impl TimerScheduler {
+    /// Timers due at `watermark`, in time order. They stay scheduled until
+    /// removed once their callback has run.
+    pub fn due(&self, watermark: i64) -> anyhow::Result<Vec<(i64, Vec<u8>)>> {
+        self.state
+            .scan_until(&encode_i64(watermark.saturating_add(1)))?
+            .into_iter()
+            .map(|(k, _)| Ok((decode_i64(&k[..8])?, k[8..].to_vec())))
+            .collect()
+    }
+
+    pub async fn remove(&self, at: i64, key: &[u8]) -> anyhow::Result<()> {
+        self.state.delete(&timer_key(key, at)).await
+    }
+}
+
+impl timers::Host for TimerScheduler {
+    async fn schedule_callback(&mut self, key: Vec<u8>, at_ms: i64) -> Status {
+        status(self.state.put(&timer_key(&key, at_ms), &[]).await)
+    }
+
+    async fn cancel_callback(&mut self, key: Vec<u8>, at_ms: i64) -> Status {
+        status(self.state.delete(&timer_key(&key, at_ms)).await)
+    }
+}
+
+fn timer_key(key: &[u8], at_ms: i64) -> Vec<u8> {
+    [encode_i64(at_ms).as_slice(), key].concat()
+}
+
+fn status(res: anyhow::Result<()>) -> Status {
+    match res {
+        Ok(()) => Status::Ok,
+        Err(e) => {
+            error!(timer_error=?e);
+            Status::Error
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateStore;
+    #[tokio::test]
+    async fn test_due() {
+        let store = StateStore::new(None, None, None).unwrap();
+        let mut timers = TimerScheduler {
+            state: store.partition(&tree_name("t"), 0).unwrap(),
+        };
+        timers::Host::schedule_callback(&mut timers, b"b".to_vec(), 20).await;
+        timers::Host::schedule_callback(&mut timers, b"a".to_vec(), 10).await;
+        timers::Host::schedule_callback(&mut timers, b"c".to_vec(), 30).await;
+        timers::Host::cancel_callback(&mut timers, b"c".to_vec(), 30).await;
+        assert!(timers.due(5).unwrap().is_empty());
+        let due = timers.due(20).unwrap();
+        assert_eq!(due, vec![(10, b"a".to_vec()), (20, b"b".to_vec())]);
+        assert_eq!(timers.due(20).unwrap(), due);
+        timers.remove(10, b"a").await.unwrap();
+        assert_eq!(timers.due(100).unwrap(), vec![(20, b"b".to_vec())]);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+
+use crate::component::watermark;
+use crate::conf;
+
+/// Event-time progress of the flow. The watermark of a partition trails the
+/// highest record timestamp seen in it by the configured out-of-orderness
+/// bound. The flow watermark is the lowest partition watermark, leaving out
+/// partitions that have been idle for longer than the idle timeout unless all
+/// of them are. Records without a timestamp only count as activity.
+#[derive(Clone, Debug, Default)]
+pub struct Watermarks {
+    max_out_of_orderness: i64,
+    idle_timeout: Option<Duration>,
+    partitions: Arc<Mutex<BTreeMap<i32, PartitionProgress>>>,
+}
+
+#[derive(Debug)]
+struct PartitionProgress {
+    max_timestamp: Option<i64>,
+    last_seen: Instant,
+}
+
+impl Watermarks {
+    pub fn new(cfg: &conf::WatermarkConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            max_out_of_orderness: i64::try_from(cfg.max_out_of_orderness_ms)
+                .with_context(|| "max_out_of_orderness_ms is out of range")?,
+            idle_timeout: cfg.idle_timeout_secs.map(Duration::from_secs),
+            partitions: Arc::new(Mutex::new(BTreeMap::new())),
+        })
+    }
+
+    pub fn observe(&self, partition: i32, timestamp: i64) -> anyhow::Result<()> {
+        let mut partitions = self
+            .partitions
+            .lock()
+            .map_err(|_| anyhow!("Watermark lock poisoned"))?;
+        let progress = partitions
+            .entry(partition)
+            .or_insert_with(|| PartitionProgress {
+                max_timestamp: None,
+                last_seen: Instant::now(),
+            });
+        progress.last_seen = Instant::now();
+        if timestamp >= 0 {
+            progress.max_timestamp = Some(
+                progress
+                    .max_timestamp
+                    .map_or(timestamp, |m| m.max(timestamp)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Stops tracking partitions that are no longer assigned.
+    pub fn remove(&self, partitions: &[i32]) -> anyhow::Result<()> {
+        let mut tracked = self
+            .partitions
+            .lock()
+            .map_err(|_| anyhow!("Watermark lock poisoned"))?;
+        for partition in partitions {
+            tracked.remove(partition);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, partition: i32) -> Option<i64> {
+        self.partitions
+            .lock()
+            .ok()
+            .and_then(|p| p.get(&partition).and_then(|p| self.watermark(p)))
+    }
+
+    /// Watermarks of all partitions that have seen a timestamp.
+    pub fn partitions(&self) -> Vec<(i32, i64)> {
+        self.partitions
+            .lock()
+            .map(|p| {
+                p.iter()
+                    .filter_map(|(partition, p)| self.watermark(p).map(|w| (*partition, w)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Watermarks the timers of each partition fire at. Partitions left out
+    /// of the flow watermark for being idle catch up with it, so that their
+    /// timers still fire.
+    pub fn timer_watermarks(&self) -> Vec<(i32, i64)> {
+        let flow = self.flow();
+        self.partitions()
+            .into_iter()
+            .map(|(partition, w)| (partition, flow.map_or(w, |f| f.max(w))))
+            .collect()
+    }
+
+    pub fn flow(&self) -> Option<i64> {
+        let partitions = self.partitions.lock().ok()?;
+        let active = partitions
+            .values()
+            .filter(|p| !self.is_idle(p))
+            .filter_map(|p| self.watermark(p))
+            .min();
+        active.or_else(|| partitions.values().filter_map(|p| self.watermark(p)).max())
+    }
+
+    fn watermark(&self, progress: &PartitionProgress) -> Option<i64> {
+        progress
+            .max_timestamp
+            .map(|m| m.saturating_sub(self.max_out_of_orderness))
+    }
+
+    fn is_idle(&self, progress: &PartitionProgress) -> bool {
+        self.idle_timeout
+            .is_some_and(|t| progress.last_seen.elapsed() >= t)
+    }
+}
+
+/// Watermarks as seen by a guest processing a record of `partition`.
+#[derive(Clone, Debug)]
+pub struct WatermarkView {
+    pub watermarks: Watermarks,
+    pub partition: i32,
+}
+
+impl watermark::Host for WatermarkView {
+    async fn partition_watermark(&mut self) -> Option<i64> {
+        self.watermarks.get(self.partition)
+    }
+
+    async fn flow_watermark(&mut self) -> Option<i64> {
+        self.watermarks.flow()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_watermarks() {
+        let cfg = conf::WatermarkConfig {
+            max_out_of_orderness_ms: 5,
+            idle_timeout_secs: None,
+        };
+        let watermarks = Watermarks::new(&cfg).unwrap();
+        watermarks.observe(0, 100).unwrap();
+        watermarks.observe(0, 90).unwrap();
+        watermarks.observe(1, 50).unwrap();
+        watermarks.observe(2, -1).unwrap();
+        assert_eq!(watermarks.get(0), Some(95));
+        assert_eq!(watermarks.get(2), None);
+        assert_eq!(watermarks.flow(), Some(45));
+        watermarks.remove(&[1]).unwrap();
+        assert_eq!(watermarks.flow(), Some(95));
+
+        let idle = Watermarks::new(&conf::WatermarkConfig {
+            idle_timeout_secs: Some(0),
+            ..cfg
+        })
+        .unwrap();
+        idle.observe(0, 100).unwrap();
+        idle.observe(1, 50).unwrap();
+        assert_eq!(idle.flow(), Some(95));
+        assert_eq!(idle.timer_watermarks(), vec![(0, 95), (1, 95)]);
+    }
+}
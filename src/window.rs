@@ -0,0 +1,334 @@
+use anyhow::{bail, Context};
+
+use crate::component::Window;
+use crate::conf::{self, WindowKind};
+use crate::pipeline::Stage;
+use crate::record::Record;
+use crate::state::{decode_i64, encode_i64, PartitionState, StateStore};
+
+/// Event-time windows of a stage. Buffered values live in the state store
+/// next to the guest state, in a `{processor}#windows` tree keyed by window
+/// end, start, record key and offset, so that due windows can be read with a
+/// single range scan. Open session windows are also indexed by record key in
+/// a `{processor}#sessions` tree so they can be merged.
+pub struct Windowing {
+    kind: WindowKind,
+    allowed_lateness: i64,
+    /// Pipeline receiving late records, which are dropped when `None`.
+    pub late: Option<Vec<Stage>>,
+    // Records of the same partition are processed concurrently.
+    lock: tokio::sync::Mutex<()>,
+}
+
+/// A window the watermark has passed, with the values buffered for `key` in
+/// offset order.
+pub struct ClosedWindow {
+    pub key: Vec<u8>,
+    pub window: Window,
+    pub state: Vec<Vec<u8>>,
+}
+
+impl ClosedWindow {
+    /// Input for the records emitted by `on-window-close`, which take the key
+    /// and the last timestamp of the window.
+    pub fn record(&self, partition: i32) -> Record {
+        Record {
+            key: Some(self.key.clone()),
+            partition,
+            timestamp: self.window.end - 1,
+            ..Default::default()
+        }
+    }
+}
+
+impl Windowing {
+    pub fn new(cfg: &conf::WindowConfig, late: Option<Vec<Stage>>) -> anyhow::Result<Self> {
+        match cfg.kind {
+            WindowKind::Tumbling { size_ms: 0 }
+            | WindowKind::Hopping { size_ms: 0, .. }
+            | WindowKind::Hopping { advance_ms: 0, .. }
+            | WindowKind::Session { gap_ms: 0 } => bail!("Window sizes must be positive"),
+            WindowKind::Hopping {
+                size_ms,
+                advance_ms,
+            } if advance_ms > size_ms => {
+                bail!("Hopping window advance must not exceed its size")
+            }
+            _ => {}
+        }
+        Ok(Self {
+            kind: cfg.kind,
+            allowed_lateness: millis(cfg.allowed_lateness_ms)?,
+            late,
+            lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Buffers `records` into their windows and removes the windows that
+    /// `watermark` closed. Returns the records whose windows were all closed
+    /// already, and the closed windows.
+    pub async fn update(
+        &self,
+        store: &StateStore,
+        processor: &str,
+        partition: i32,
+        records: &[Record],
+        watermark: Option<i64>,
+    ) -> anyhow::Result<(Vec<Record>, Vec<ClosedWindow>)> {
+        let _guard = self.lock.lock().await;
+        let windows = store.partition(&format!("{processor}#windows"), partition)?;
+        let sessions = store.partition(&format!("{processor}#sessions"), partition)?;
+        // Windows ending at or before this are closed.
+        let closed_until = watermark.map(|w| w.saturating_sub(self.allowed_lateness));
+        let is_closed = |end: i64| closed_until.is_some_and(|c| end <= c);
+        let mut late = Vec::new();
+        for (seq, rec) in records.iter().enumerate() {
+            let key = rec.key.as_deref().unwrap_or_default();
+            let value = rec.value.as_deref().unwrap_or_default();
+            let entry = entry_suffix(rec.offset, seq);
+            match self.kind {
+                WindowKind::Session { gap_ms } => {
+                    let window = Window {
+                        start: rec.timestamp,
+                        end: rec.timestamp.saturating_add(millis(gap_ms)?),
+                    };
+                    if is_closed(window.end) {
+                        late.push(rec.clone());
+                        continue;
+                    }
+                    let window = merge_sessions(&windows, &sessions, key, window).await?;
+                    windows
+                        .put(&[window_prefix(&window, key), entry].concat(), value)
+                        .await?;
+                }
+                _ => {
+                    let open: Vec<Window> = self
+                        .assign(rec.timestamp)?
+                        .into_iter()
+                        .filter(|w| !is_closed(w.end))
+                        .collect();
+                    if open.is_empty() {
+                        late.push(rec.clone());
+                        continue;
+                    }
+                    for window in open {
+                        windows
+                            .put(
+                                &[window_prefix(&window, key), entry.clone()].concat(),
+                                value,
+                            )
+                            .await?;
+                    }
+                }
+            }
+        }
+        let closed = match closed_until {
+            Some(c) => self.close(&windows, &sessions, c).await?,
+            None => Vec::new(),
+        };
+        Ok((late, closed))
+    }
+
+    /// Tumbling and hopping windows containing `timestamp`.
+    fn assign(&self, timestamp: i64) -> anyhow::Result<Vec<Window>> {
+        let (size, advance) = match self.kind {
+            WindowKind::Tumbling { size_ms } => (millis(size_ms)?, millis(size_ms)?),
+            WindowKind::Hopping {
+                size_ms,
+                advance_ms,
+            } => (millis(size_ms)?, millis(advance_ms)?),
+            WindowKind::Session { .. } => bail!("Session windows are not assigned up front"),
+        };
+        let mut start = timestamp - timestamp.rem_euclid(advance);
+        let mut windows = Vec::new();
+        while start > timestamp - size {
+            windows.push(Window {
+                start,
+                end: start + size,
+            });
+            start -= advance;
+        }
+        Ok(windows)
+    }
+
+    async fn close(
+        &self,
+        windows: &PartitionState,
+        sessions: &PartitionState,
+        closed_until: i64,
+    ) -> anyhow::Result<Vec<ClosedWindow>> {
+        let mut closed: Vec<(Vec<u8>, ClosedWindow)> = Vec::new();
+        for (k, v) in windows.scan_until(&encode_i64(closed_until.saturating_add(1)))? {
+            let prefix = &k[..k.len() - ENTRY_SUFFIX_LEN];
+            match closed.last_mut() {
+                Some((p, c)) if p.as_slice() == prefix => c.state.push(v),
+                _ => {
+                    let (window, key) = decode_prefix(prefix)?;
+                    closed.push((
+                        prefix.to_vec(),
+                        ClosedWindow {
+                            key,
+                            window,
+                            state: vec![v],
+                        },
+                    ));
+                }
+            }
+            windows.delete(&k).await?;
+        }
+        if let WindowKind::Session { .. } = self.kind {
+            for (_, c) in &closed {
+                sessions
+                    .delete(&session_key(&c.key, c.window.start))
+                    .await?;
+            }
+        }
+        Ok(closed.into_iter().map(|(_, c)| c).collect())
+    }
+}
+
+/// Merges `window` with the open sessions of `key` it overlaps, moving their
+/// buffered values, and returns the merged session.
+async fn merge_sessions(
+    windows: &PartitionState,
+    sessions: &PartitionState,
+    key: &[u8],
+    mut window: Window,
+) -> anyhow::Result<Window> {
+    let mut moved = Vec::new();
+    for (k, v) in sessions.scan_prefix(&key_prefix(key))? {
+        let start = decode_i64(&k[k.len() - 8..])?;
+        let end = decode_i64(&v)?;
+        if start >= window.end || window.start >= end {
+            continue;
+        }
+        let session = Window { start, end };
+        let prefix = window_prefix(&session, key);
+        for (ek, ev) in windows.scan_prefix(&prefix)? {
+            moved.push((ek[prefix.len()..].to_vec(), ev));
+            windows.delete(&ek).await?;
+        }
+        sessions.delete(&k).await?;
+        window = Window {
+            start: window.start.min(start),
+            end: window.end.max(end),
+        };
+    }
+    let prefix = window_prefix(&window, key);
+    for (suffix, v) in moved {
+        windows.put(&[prefix.clone(), suffix].concat(), &v).await?;
+    }
+    sessions
+        .put(&session_key(key, window.start), &encode_i64(window.end))
+        .await?;
+    Ok(window)
+}
+
+const ENTRY_SUFFIX_LEN: usize = 12;
+
+fn millis(ms: u64) -> anyhow::Result<i64> {
+    i64::try_from(ms).with_context(|| format!("Duration out of range: {ms}ms"))
+}
+
+fn key_prefix(key: &[u8]) -> Vec<u8> {
+    [&(key.len() as u32).to_be_bytes(), key].concat()
+}
+
+fn window_prefix(window: &Window, key: &[u8]) -> Vec<u8> {
+    [
+        encode_i64(window.end),
+        encode_i64(window.start),
+        key_prefix(key),
+    ]
+    .concat()
+}
+
+fn decode_prefix(prefix: &[u8]) -> anyhow::Result<(Window, Vec<u8>)> {
+    if prefix.len() < 20 {
+        bail!("Invalid window entry");
+    }
+    let window = Window {
+        end: decode_i64(&prefix[..8])?,
+        start: decode_i64(&prefix[8..16])?,
+    };
+    Ok((window, prefix[20..].to_vec()))
+}
+
+fn entry_suffix(offset: i64, seq: usize) -> Vec<u8> {
+    [encode_i64(offset), (seq as u32).to_be_bytes().to_vec()].concat()
+}
+
+fn session_key(key: &[u8], start: i64) -> Vec<u8> {
+    [key_prefix(key), encode_i64(start)].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rec(key: &str, value: &str, offset: i64, timestamp: i64) -> Record {
+        Record {
+            key: Some(key.as_bytes().to_vec()),
+            value: Some(value.as_bytes().to_vec()),
+            offset,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn windowing(kind: WindowKind) -> Windowing {
+        let cfg = conf::WindowConfig {
+            kind,
+            allowed_lateness_ms: 0,
+            late_records: conf::LateRecords::Drop,
+        };
+        Windowing::new(&cfg, None).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_windows() {
+        let store = StateStore::new(None, None, None).unwrap();
+        let tumbling = windowing(WindowKind::Tumbling { size_ms: 10 });
+        let (late, closed) = tumbling
+            .update(
+                &store,
+                "t",
+                0,
+                &[rec("a", "1", 0, 3), rec("a", "2", 1, 7)],
+                Some(7),
+            )
+            .await
+            .unwrap();
+        assert!(late.is_empty() && closed.is_empty());
+        let (late, closed) = tumbling
+            .update(&store, "t", 0, &[rec("a", "3", 2, 12)], Some(12))
+            .await
+            .unwrap();
+        assert!(late.is_empty());
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].window.start, closed[0].window.end), (0, 10));
+        assert_eq!(closed[0].state, vec![b"1".to_vec(), b"2".to_vec()]);
+        let (late, _) = tumbling
+            .update(&store, "t", 0, &[rec("a", "4", 3, 5)], Some(12))
+            .await
+            .unwrap();
+        assert_eq!(late.len(), 1);
+
+        let session = windowing(WindowKind::Session { gap_ms: 5 });
+        let records = [rec("a", "1", 0, 0), rec("a", "2", 1, 4)];
+        let (_, closed) = session
+            .update(&store, "s", 0, &records, Some(4))
+            .await
+            .unwrap();
+        assert!(closed.is_empty());
+        let (late, closed) = session
+            .update(&store, "s", 0, &[rec("a", "3", 2, 8)], Some(8))
+            .await
+            .unwrap();
+        assert!(late.is_empty() && closed.is_empty());
+        let (_, closed) = session.update(&store, "s", 0, &[], Some(13)).await.unwrap();
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].window.start, closed[0].window.end), (0, 13));
+        assert_eq!(closed[0].state.len(), 3);
+    }
+}